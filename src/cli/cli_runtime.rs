@@ -0,0 +1,329 @@
+//! A container runtime driven through its command line client, used for Podman and nerdctl
+//! which don't reliably expose a Docker compatible API socket. Commands are run with
+//! structured arguments, never through a shell
+
+use crate::cli::container_runtime::{
+    binary_on_path, ContainerRuntime, ExecOutput, RunOptions, RuntimeError, RuntimeResult,
+    RUNTIME_NERDCTL, RUNTIME_PODMAN,
+};
+use std::process::{Command, Output};
+
+// unprivileged processes can't bind ports below this one by default
+const FIRST_UNPRIVILEGED_PORT: u16 = 1024;
+
+pub struct CliRuntime {
+    // the client binary, podman or nerdctl
+    pub binary: String,
+}
+
+impl CliRuntime {
+    pub fn new(binary: &str) -> CliRuntime {
+        CliRuntime {
+            binary: binary.to_string(),
+        }
+    }
+
+    fn is_podman(&self) -> bool {
+        self.binary == RUNTIME_PODMAN
+    }
+
+    fn output(&self, args: &[String]) -> RuntimeResult<Output> {
+        if !binary_on_path(&self.binary) {
+            return Err(RuntimeError::NotInstalled(self.name()));
+        }
+
+        Ok(Command::new(&self.binary).args(args).output()?)
+    }
+
+    // runs the client, failing with its stderr when it exits unsuccessfully
+    fn command(&self, args: &[&str]) -> RuntimeResult<String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let output = self.output(&args)?;
+
+        if !output.status.success() {
+            return Err(RuntimeError::Api {
+                status: output.status.code().unwrap_or(0) as u16,
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    // rootless Podman runs containers in a user namespace, with a few restrictions
+    fn is_rootless(&self) -> bool {
+        self.is_podman()
+            && self
+                .command(&["info", "--format", "{{.Host.Security.Rootless}}"])
+                .map(|out| out.trim() == "true")
+                .unwrap_or(false)
+    }
+
+    fn network_exists(&self, network: &str) -> RuntimeResult<bool> {
+        let networks = self.command(&["network", "ls", "--format", "{{.Name}}"])?;
+
+        Ok(networks.lines().any(|n| n.trim() == network))
+    }
+
+    // the arguments of `run`, split out so they can be checked without a runtime
+    fn run_args(&self, options: &RunOptions, rootless: bool) -> RuntimeResult<Vec<String>> {
+        let mut args = vec![
+            String::from("run"),
+            String::from("-d"),
+            String::from("--name"),
+            options.name.clone(),
+        ];
+
+        for (host_port, container_port) in &options.ports {
+            if rootless && *host_port < FIRST_UNPRIVILEGED_PORT {
+                return Err(RuntimeError::Unsupported(format!(
+                    "rootless {} can't publish port {}, use a port of {} or above",
+                    self.name(),
+                    host_port,
+                    FIRST_UNPRIVILEGED_PORT
+                )));
+            }
+
+            args.push(String::from("-p"));
+            args.push(format!("{}:{}", host_port, container_port));
+        }
+
+        if let Some(cpus) = options.cpus {
+            args.push(format!("--cpus={}", cpus));
+        }
+        if let Some(memory) = options.memory_bytes {
+            args.push(format!("--memory={}b", memory));
+            args.push(format!("--memory-swap={}b", memory));
+        }
+        if let Some(storage) = options.storage_bytes {
+            args.push(String::from("--storage-opt"));
+            args.push(format!("size={}b", storage));
+        }
+        if let Some(network) = &options.network {
+            args.push(format!("--network={}", network));
+        }
+
+        for (source, target) in &options.volumes {
+            args.push(String::from("-v"));
+            args.push(format!("{}:{}", source, target));
+        }
+
+        for env in &options.env {
+            args.push(String::from("-e"));
+            args.push(env.clone());
+        }
+
+        // the client takes a single entrypoint, its arguments go before the command
+        let mut cmd = vec![];
+        if let Some(entrypoint) = &options.entrypoint {
+            if let Some((first, rest)) = entrypoint.split_first() {
+                args.push(format!("--entrypoint={}", first));
+                cmd.extend(rest.iter().cloned());
+            }
+        }
+        if let Some(options_cmd) = &options.cmd {
+            cmd.extend(options_cmd.iter().cloned());
+        }
+
+        args.push(options.image.clone());
+        args.extend(cmd);
+
+        Ok(args)
+    }
+}
+
+impl ContainerRuntime for CliRuntime {
+    fn name(&self) -> String {
+        match self.binary.as_str() {
+            RUNTIME_PODMAN => String::from("Podman"),
+            RUNTIME_NERDCTL => String::from("nerdctl"),
+            other => other.to_string(),
+        }
+    }
+
+    fn ping(&self) -> RuntimeResult {
+        self.command(&["info"]).map(|_| ()).map_err(|e| match e {
+            RuntimeError::Api { .. } => RuntimeError::NotRunning(self.name()),
+            e => e,
+        })
+    }
+
+    fn container_id(&self, name: &str) -> RuntimeResult<Option<String>> {
+        let containers = self.command(&["ps", "-a", "--format", "{{.ID}} {{.Names}}"])?;
+
+        Ok(containers.lines().find_map(|line| {
+            let mut parts = line.split_whitespace();
+            let id = parts.next()?;
+
+            parts
+                .any(|n| n.trim_start_matches('/') == name)
+                .then(|| id.to_string())
+        }))
+    }
+
+    fn build_image(&self, context_dir: &str, tag: &str) -> RuntimeResult {
+        self.command(&["build", "-t", tag, context_dir])?;
+
+        Ok(())
+    }
+
+    fn run(&self, options: &RunOptions) -> RuntimeResult<String> {
+        let args = self.run_args(options, self.is_rootless())?;
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+        Ok(self.command(&args)?.trim().to_string())
+    }
+
+    fn start(&self, name: &str) -> RuntimeResult {
+        self.command(&["start", name])?;
+
+        Ok(())
+    }
+
+    fn stop(&self, name: &str) -> RuntimeResult {
+        self.command(&["stop", name])?;
+
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> RuntimeResult {
+        self.command(&["rm", name])?;
+
+        Ok(())
+    }
+
+    fn rename(&self, name: &str, new_name: &str) -> RuntimeResult {
+        self.command(&["rename", name, new_name])?;
+
+        Ok(())
+    }
+
+    fn exec(&self, name: &str, cmd: &[String]) -> RuntimeResult<ExecOutput> {
+        let mut args = vec![String::from("exec"), name.to_string()];
+        args.extend(cmd.iter().cloned());
+
+        let output = self.output(&args)?;
+
+        Ok(ExecOutput {
+            exit_code: output.status.code().unwrap_or(-1) as i64,
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+
+    fn update_resources(&self, name: &str, cpus: f64, memory_bytes: u64) -> RuntimeResult {
+        self.command(&[
+            "update",
+            &format!("--cpus={}", cpus),
+            &format!("--memory={}b", memory_bytes),
+            &format!("--memory-swap={}b", memory_bytes),
+            name,
+        ])?;
+
+        Ok(())
+    }
+
+    // neither client can size a container's writable layer with the default storage drivers
+    fn supports_storage_limit(&self) -> bool {
+        false
+    }
+
+    fn create_network(&self, network: &str) -> RuntimeResult {
+        if !self.network_exists(network)? {
+            self.command(&["network", "create", network])?;
+        }
+
+        Ok(())
+    }
+
+    fn connect_network(&self, network: &str, name: &str) -> RuntimeResult {
+        let networks = self.command(&[
+            "inspect",
+            "-f",
+            "{{range $k, $v := .NetworkSettings.Networks}}{{$k}} {{end}}",
+            name,
+        ])?;
+
+        if networks.split_whitespace().any(|n| n == network) {
+            return Ok(());
+        }
+
+        // nerdctl can only attach networks when a container is created
+        if !self.is_podman() {
+            return Err(RuntimeError::Unsupported(format!(
+                "{} can't connect the existing container {} to network {}, delete the instance and apply again",
+                self.name(),
+                name,
+                network
+            )));
+        }
+
+        self.command(&["network", "connect", network, name])?;
+
+        Ok(())
+    }
+
+    fn remove_volume(&self, name: &str) -> RuntimeResult {
+        let volumes = self.command(&["volume", "ls", "--format", "{{.Name}}"])?;
+
+        if volumes.lines().any(|v| v.trim() == name) {
+            self.command(&["volume", "rm", name])?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_network(&self, network: &str) -> RuntimeResult {
+        if self.network_exists(network)? {
+            self.command(&["network", "rm", network])?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_args_test() {
+        let options = RunOptions {
+            name: String::from("app-replica-1"),
+            image: String::from("postgres-app"),
+            ports: vec![(5433, 5432)],
+            cpus: Some(0.5),
+            memory_bytes: Some(1024),
+            network: Some(String::from("tembo-app")),
+            volumes: vec![(
+                String::from("tembo-app-pg15"),
+                String::from("/var/lib/postgresql/data"),
+            )],
+            env: vec![String::from("PGPASSWORD=postgres")],
+            entrypoint: Some(vec![String::from("bash")]),
+            cmd: Some(vec![String::from("-c"), String::from("exec postgres")]),
+            ..RunOptions::default()
+        };
+
+        let podman = CliRuntime::new(RUNTIME_PODMAN);
+        assert_eq!(
+            podman.run_args(&options, true).unwrap().join(" "),
+            "run -d --name app-replica-1 -p 5433:5432 --cpus=0.5 --memory=1024b --memory-swap=1024b \
+             --network=tembo-app -v tembo-app-pg15:/var/lib/postgresql/data -e PGPASSWORD=postgres \
+             --entrypoint=bash postgres-app -c exec postgres"
+        );
+
+        let nerdctl = CliRuntime::new(RUNTIME_NERDCTL);
+        assert_eq!(
+            nerdctl.run_args(&options, false).unwrap(),
+            podman.run_args(&options, true).unwrap()
+        );
+
+        let privileged = RunOptions {
+            ports: vec![(543, 5432)],
+            ..options
+        };
+        assert!(podman.run_args(&privileged, true).is_err());
+        assert!(podman.run_args(&privileged, false).is_ok());
+    }
+}
@@ -0,0 +1,359 @@
+//! Client for the Tembo Cloud API, used when the current context targets tembo-cloud
+
+use crate::cli::context::Profile;
+use crate::cli::tembo_config::{InstanceSettings, DEFAULT_DATABASE};
+use crate::Result;
+use anyhow::{bail, Context};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use simplelog::*;
+use spinners::{Spinner, Spinners};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const READY_STATE: &str = "Up";
+const ERROR_STATE: &str = "Error";
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const READY_TIMEOUT: Duration = Duration::from_secs(600);
+// how long an updated instance may stay up before the update is taken as needing no restart
+const UPDATE_PICKUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct CloudClient {
+    client: Client,
+    host: String,
+    access_token: String,
+    org_id: String,
+}
+
+// an instance as returned by the Tembo Cloud API, only the fields the CLI uses
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloudInstance {
+    pub instance_id: String,
+    pub instance_name: String,
+    pub state: String,
+}
+
+// request body for creating or updating an instance
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct InstanceSpec {
+    pub instance_name: String,
+    pub environment: String,
+    pub stack_type: String,
+    pub cpu: String,
+    pub memory: String,
+    pub storage: String,
+    pub replicas: u32,
+    pub extensions: Vec<CloudExtension>,
+    pub trunk_installs: Vec<CloudTrunkInstall>,
+    pub postgres_configs: Vec<CloudPostgresConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloudExtension {
+    pub name: String,
+    pub locations: Vec<CloudExtensionLocation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloudExtensionLocation {
+    pub database: String,
+    pub schema: Option<String>,
+    pub enabled: bool,
+    pub version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloudTrunkInstall {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloudPostgresConfig {
+    pub name: String,
+    pub value: String,
+}
+
+impl InstanceSpec {
+    // translates the settings of a tembo.toml instance into a Tembo Cloud request body
+    pub fn from_settings(instance_setting: &InstanceSettings) -> Result<InstanceSpec> {
+        let mut names: Vec<&String> = instance_setting.extensions.keys().collect();
+        names.sort();
+
+        let mut extensions = vec![];
+        let mut trunk_installs = vec![];
+
+        for name in names {
+            let extension = &instance_setting.extensions[name];
+
+            let locations = if extension.locations.is_empty() {
+                vec![CloudExtensionLocation {
+                    database: DEFAULT_DATABASE.to_string(),
+                    schema: None,
+                    enabled: extension.enabled,
+                    version: extension
+                        .extension_version
+                        .clone()
+                        .or_else(|| extension.trunk_project_version.clone()),
+                }]
+            } else {
+                extension
+                    .locations
+                    .iter()
+                    .map(|location| CloudExtensionLocation {
+                        database: location.database.clone(),
+                        schema: location.schema.clone(),
+                        enabled: extension.enabled,
+                        version: location
+                            .version
+                            .clone()
+                            .or_else(|| extension.extension_version.clone())
+                            .or_else(|| extension.trunk_project_version.clone()),
+                    })
+                    .collect()
+            };
+
+            extensions.push(CloudExtension {
+                name: name.clone(),
+                locations,
+            });
+
+            if let Some(trunk_project) = &extension.trunk_project {
+                trunk_installs.push(CloudTrunkInstall {
+                    name: trunk_project.clone(),
+                    version: extension.trunk_project_version.clone(),
+                });
+            }
+        }
+
+        let mut postgres_configs: Vec<CloudPostgresConfig> = instance_setting
+            .postgres_config_pairs()?
+            .into_iter()
+            .map(|(name, value)| CloudPostgresConfig { name, value })
+            .collect();
+        postgres_configs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(InstanceSpec {
+            instance_name: instance_setting.instance_name.clone(),
+            environment: instance_setting.environment.clone(),
+            stack_type: instance_setting.stack_type.clone(),
+            cpu: instance_setting.cpu.clone(),
+            memory: instance_setting.memory.clone(),
+            storage: instance_setting.storage.clone(),
+            replicas: instance_setting.replicas,
+            extensions,
+            trunk_installs,
+            postgres_configs,
+        })
+    }
+}
+
+impl CloudClient {
+    pub fn new(profile: &Profile, org_id: &str) -> CloudClient {
+        CloudClient {
+            client: Client::new(),
+            host: profile.tembo_host.trim_end_matches('/').to_string(),
+            access_token: profile.tembo_access_token.clone(),
+            org_id: org_id.to_string(),
+        }
+    }
+
+    fn instances_url(&self) -> String {
+        format!("{}/api/v1/orgs/{}/instances", self.host, self.org_id)
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let res = request.bearer_auth(&self.access_token).send()?;
+        let status = res.status();
+
+        if !status.is_success() {
+            let body = res.text().unwrap_or_default();
+            bail!("Tembo Cloud API returned {}: {}", status, body);
+        }
+
+        Ok(res)
+    }
+
+    pub fn list_instances(&self) -> Result<Vec<CloudInstance>> {
+        let res = self.send(self.client.get(self.instances_url()))?;
+
+        res.json()
+            .with_context(|| "Unable to parse instances from Tembo Cloud")
+    }
+
+    pub fn get_instance(&self, instance_id: &str) -> Result<CloudInstance> {
+        let url = format!("{}/{}", self.instances_url(), instance_id);
+        let res = self.send(self.client.get(url))?;
+
+        res.json()
+            .with_context(|| "Unable to parse instance from Tembo Cloud")
+    }
+
+    pub fn create_instance(&self, spec: &InstanceSpec) -> Result<CloudInstance> {
+        let res = self.send(self.client.post(self.instances_url()).json(spec))?;
+
+        res.json()
+            .with_context(|| "Unable to parse created instance from Tembo Cloud")
+    }
+
+    pub fn patch_instance(&self, instance_id: &str, spec: &InstanceSpec) -> Result<CloudInstance> {
+        let url = format!("{}/{}", self.instances_url(), instance_id);
+        let res = self.send(self.client.patch(url).json(spec))?;
+
+        res.json()
+            .with_context(|| "Unable to parse updated instance from Tembo Cloud")
+    }
+
+    // creates the instance if none exists with the same name, otherwise patches the existing one
+    pub fn apply_instance(&self, spec: &InstanceSpec) -> Result<CloudInstance> {
+        let existing = self
+            .list_instances()?
+            .into_iter()
+            .find(|i| i.instance_name == spec.instance_name);
+
+        match existing {
+            Some(instance) => {
+                info!("Updating Tembo Cloud instance {}", spec.instance_name);
+                let instance = self.patch_instance(&instance.instance_id, spec)?;
+                self.wait_until_updating(&instance)?;
+
+                Ok(instance)
+            }
+            None => {
+                info!("Creating Tembo Cloud instance {}", spec.instance_name);
+                self.create_instance(spec)
+            }
+        }
+    }
+
+    // a patched instance keeps reporting its previous state until the update is picked up, so
+    // waiting for it to be ready right away would return before the update is applied
+    fn wait_until_updating(&self, instance: &CloudInstance) -> Result<()> {
+        if instance.state != READY_STATE {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+
+        while started.elapsed() < UPDATE_PICKUP_TIMEOUT {
+            if self.get_instance(&instance.instance_id)?.state != READY_STATE {
+                return Ok(());
+            }
+
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    // polls the instance until it reports being up, failing on error or timeout
+    pub fn wait_until_ready(&self, instance_id: &str) -> Result<CloudInstance> {
+        let mut sp = Spinner::new(
+            Spinners::Line,
+            "Waiting for Tembo Cloud instance to be ready".into(),
+        );
+        let started = Instant::now();
+
+        loop {
+            let instance = self.get_instance(instance_id)?;
+
+            if instance.state == READY_STATE {
+                sp.stop_with_message(format!("- Instance {} is up", instance.instance_name));
+                return Ok(instance);
+            }
+
+            if instance.state == ERROR_STATE {
+                sp.stop_with_newline();
+                bail!("Instance {} is in an error state", instance.instance_name);
+            }
+
+            if started.elapsed() > READY_TIMEOUT {
+                sp.stop_with_newline();
+                bail!(
+                    "Timed out waiting for instance {}, last state: {}",
+                    instance.instance_name,
+                    instance.state
+                );
+            }
+
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::tembo_config::{Extension, ExtensionLocation};
+    use std::collections::HashMap;
+    use toml::Value;
+
+    #[test]
+    fn from_settings_test() {
+        let mut extensions = HashMap::new();
+        extensions.insert(
+            String::from("pgmq"),
+            Extension {
+                enabled: true,
+                trunk_project: Some(String::from("pgmq")),
+                trunk_project_version: Some(String::from("0.24.0")),
+                extension_version: None,
+                locations: vec![],
+            },
+        );
+        extensions.insert(
+            String::from("vector"),
+            Extension {
+                enabled: true,
+                trunk_project: None,
+                trunk_project_version: None,
+                extension_version: None,
+                locations: vec![ExtensionLocation {
+                    database: String::from("app"),
+                    schema: Some(String::from("embeddings")),
+                    version: Some(String::from("0.5.0")),
+                }],
+            },
+        );
+        let mut postgres_configurations = HashMap::new();
+        postgres_configurations.insert(
+            String::from("statement_timeout"),
+            Value::String(String::from("60")),
+        );
+
+        let instance_setting = InstanceSettings {
+            instance_name: String::from("app"),
+            port: 5432,
+            environment: String::from("prod"),
+            stack_type: String::from("Standard"),
+            pg_version: 15,
+            template_dir: None,
+            cpu: String::from("1"),
+            memory: String::from("2Gi"),
+            storage: String::from("10Gi"),
+            replicas: 1,
+            postgres_configurations,
+            extensions,
+            databases: HashMap::new(),
+            roles: HashMap::new(),
+            default_privileges: vec![],
+            extension_libraries: HashMap::new(),
+        };
+
+        let spec = InstanceSpec::from_settings(&instance_setting).unwrap();
+
+        assert_eq!(spec.instance_name, "app");
+        assert_eq!(spec.environment, "prod");
+        assert_eq!(spec.extensions[0].name, "pgmq");
+        assert!(spec.extensions[0].locations[0].enabled);
+        assert_eq!(spec.extensions[0].locations[0].database, "postgres");
+        assert_eq!(spec.extensions[1].locations[0].database, "app");
+        assert_eq!(
+            spec.extensions[1].locations[0].schema,
+            Some(String::from("embeddings"))
+        );
+        assert_eq!(spec.trunk_installs[0].version, Some(String::from("0.24.0")));
+        assert_eq!(spec.postgres_configs[0].name, "statement_timeout");
+        assert_eq!(spec.postgres_configs[0].value, "60");
+    }
+}
@@ -243,6 +243,8 @@ mod tests {
             r#type: Some(String::from("standard")),
             port: Some(String::from("5432")),
             version: Some(String::from("1.1")),
+            pg_version: Some(15),
+            volume: Some(String::from("tembo-instance_name-pg15")),
             created_at: Some(Utc::now()),
             installed_extensions: vec![InstalledExtension {
                 name: Some(String::from("pgmq")),
@@ -0,0 +1,244 @@
+//! The operations the CLI needs from a container runtime, so local instances can be managed
+//! through the Docker Engine API (or a mock in tests) rather than shell strings
+
+use crate::cli::cli_runtime::CliRuntime;
+use crate::cli::context::get_current_context;
+use crate::cli::docker_engine::DockerEngine;
+use crate::Result;
+use anyhow::bail;
+use std::env;
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixStream;
+
+pub const RUNTIME_DOCKER: &str = "docker";
+pub const RUNTIME_PODMAN: &str = "podman";
+pub const RUNTIME_NERDCTL: &str = "nerdctl";
+
+// selects the runtime, taking precedence over the `runtime` of the current context
+pub const RUNTIME_ENV_VAR: &str = "TEMBO_CONTAINER_RUNTIME";
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    // the runtime's client could not be found
+    NotInstalled(String),
+    // the runtime is installed but its daemon can't be reached
+    NotRunning(String),
+    // the runtime's API rejected a request
+    Api {
+        status: u16,
+        message: String,
+    },
+    // the runtime is set up in a way tembo can't work with
+    Unsupported(String),
+    // a command run inside a container exited unsuccessfully
+    CommandFailed {
+        container_id: String,
+        exit_code: i64,
+        stderr: String,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::NotInstalled(runtime) => {
+                write!(
+                    f,
+                    "{} is not installed, please install it and try again",
+                    runtime
+                )
+            }
+            RuntimeError::NotRunning(runtime) => {
+                write!(
+                    f,
+                    "{} is not running, please start it and try again",
+                    runtime
+                )
+            }
+            RuntimeError::Api { status, message } => {
+                write!(f, "container runtime returned {}: {}", status, message)
+            }
+            RuntimeError::Unsupported(message) => f.write_str(message),
+            RuntimeError::CommandFailed {
+                container_id,
+                exit_code,
+                stderr,
+            } => write!(
+                f,
+                "command in container {} exited with {}: {}",
+                container_id,
+                exit_code,
+                stderr.trim()
+            ),
+            RuntimeError::Io(e) => write!(f, "container runtime i/o error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl From<io::Error> for RuntimeError {
+    fn from(e: io::Error) -> Self {
+        RuntimeError::Io(e)
+    }
+}
+
+pub type RuntimeResult<T = ()> = std::result::Result<T, RuntimeError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecOutput {
+    pub exit_code: i64,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// how a new container is created
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunOptions {
+    pub name: String,
+    pub image: String,
+    // (host port, container port)
+    pub ports: Vec<(u16, u16)>,
+    pub cpus: Option<f64>,
+    pub memory_bytes: Option<u64>,
+    pub storage_bytes: Option<u64>,
+    pub network: Option<String>,
+    // (volume name, container path), named volumes are created on first use
+    pub volumes: Vec<(String, String)>,
+    pub env: Vec<String>,
+    pub entrypoint: Option<Vec<String>>,
+    pub cmd: Option<Vec<String>>,
+}
+
+#[cfg_attr(test, mockall::automock)]
+pub trait ContainerRuntime {
+    // a human readable name, used in messages
+    fn name(&self) -> String;
+
+    // checks the runtime is installed and its daemon reachable
+    fn ping(&self) -> RuntimeResult;
+
+    // returns the id of the container with exactly this name, if one exists
+    fn container_id(&self, name: &str) -> RuntimeResult<Option<String>>;
+
+    fn build_image(&self, context_dir: &str, tag: &str) -> RuntimeResult;
+
+    // creates and starts a container, returning its id
+    fn run(&self, options: &RunOptions) -> RuntimeResult<String>;
+
+    fn start(&self, name: &str) -> RuntimeResult;
+
+    fn stop(&self, name: &str) -> RuntimeResult;
+
+    fn remove(&self, name: &str) -> RuntimeResult;
+
+    fn rename(&self, name: &str, new_name: &str) -> RuntimeResult;
+
+    // removes the named volume and the data in it, if it exists
+    fn remove_volume(&self, name: &str) -> RuntimeResult;
+
+    // runs a command in a running container, failing only if it couldn't be run
+    fn exec(&self, name: &str, cmd: &[String]) -> RuntimeResult<ExecOutput>;
+
+    fn update_resources(&self, name: &str, cpus: f64, memory_bytes: u64) -> RuntimeResult;
+
+    // determines if containers can be created with a storage size limit
+    fn supports_storage_limit(&self) -> bool;
+
+    fn create_network(&self, network: &str) -> RuntimeResult;
+
+    // connects the container to the network unless it's already connected
+    fn connect_network(&self, network: &str, name: &str) -> RuntimeResult;
+
+    // removes the network if it exists
+    fn remove_network(&self, network: &str) -> RuntimeResult;
+}
+
+// returns the container runtime local instances are managed with, as selected by
+// TEMBO_CONTAINER_RUNTIME or the current context, otherwise the first one found
+pub fn runtime() -> Result<Box<dyn ContainerRuntime>> {
+    let selected = env::var(RUNTIME_ENV_VAR)
+        .ok()
+        .filter(|r| !r.is_empty())
+        .or_else(|| get_current_context().ok().and_then(|e| e.runtime));
+
+    runtime_named(&selected.unwrap_or_else(detect_runtime))
+}
+
+fn runtime_named(name: &str) -> Result<Box<dyn ContainerRuntime>> {
+    let name = name.to_lowercase();
+
+    match name.as_str() {
+        RUNTIME_DOCKER => Ok(Box::new(DockerEngine::from_env()?)),
+        RUNTIME_PODMAN | RUNTIME_NERDCTL => Ok(Box::new(CliRuntime::new(&name))),
+        other => bail!(
+            "Unknown container runtime `{}`, expected one of: {}, {}, {}",
+            other,
+            RUNTIME_DOCKER,
+            RUNTIME_PODMAN,
+            RUNTIME_NERDCTL
+        ),
+    }
+}
+
+// prefers a reachable Docker daemon, then whichever client is installed, defaulting to docker.
+// A Docker host that isn't a unix socket selects docker, so the error explaining why is shown
+fn detect_runtime() -> String {
+    let reachable = DockerEngine::from_env()
+        .map(|engine| UnixStream::connect(engine.socket_path).is_ok())
+        .unwrap_or(true);
+
+    if reachable {
+        return RUNTIME_DOCKER.to_string();
+    }
+
+    [RUNTIME_DOCKER, RUNTIME_PODMAN, RUNTIME_NERDCTL]
+        .into_iter()
+        .find(|binary| binary_on_path(binary))
+        .unwrap_or(RUNTIME_DOCKER)
+        .to_string()
+}
+
+pub fn binary_on_path(binary: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(binary).is_file()))
+        .unwrap_or(false)
+}
+
+// runs a command in a container, turning a non zero exit code into an error
+pub fn exec_checked(
+    runtime: &dyn ContainerRuntime,
+    name: &str,
+    cmd: &[&str],
+) -> RuntimeResult<String> {
+    let cmd: Vec<String> = cmd.iter().map(|c| c.to_string()).collect();
+    let output = runtime.exec(name, &cmd)?;
+
+    if output.exit_code != 0 {
+        let container_id = runtime
+            .container_id(name)?
+            .unwrap_or_else(|| name.to_string());
+
+        return Err(RuntimeError::CommandFailed {
+            container_id,
+            exit_code: output.exit_code,
+            stderr: output.stderr,
+        });
+    }
+
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_named_test() {
+        assert_eq!(runtime_named("Podman").unwrap().name(), "Podman");
+        assert_eq!(runtime_named(RUNTIME_NERDCTL).unwrap().name(), "nerdctl");
+        assert!(runtime_named("rkt").is_err());
+    }
+}
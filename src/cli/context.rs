@@ -1,11 +1,19 @@
+use crate::Result;
+use anyhow::{bail, Context as AnyhowContext};
 use serde::Deserialize;
 use serde::Serialize;
+use std::fs;
+use std::io;
+
+pub const TARGET_DOCKER: &str = "docker";
+pub const TARGET_TEMBO_CLOUD: &str = "tembo-cloud";
 
 pub const CONTEXT_DEFAULT_TEXT: &str = "version = \"1.0\"
 
 [[environment]]
 name = 'local'
 target = 'docker'
+# runtime = 'podman' # docker, podman or nerdctl, detected when not set
 set = true
 
 [[environment]]
@@ -51,4 +59,98 @@ pub struct Environment {
     pub org_id: Option<String>,
     pub profile: Option<String>,
     pub set: Option<bool>,
+    // the container runtime of a docker target: docker, podman or nerdctl
+    pub runtime: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Credential {
+    pub version: String,
+    pub environment: Vec<Profile>,
+}
+
+// Profile struct holds the data from an `[[environment]]` section of the credentials file,
+// matched to a context environment through its `profile` name
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub tembo_access_token: String,
+    pub tembo_host: String,
+}
+
+// returns the environment from the context file that is currently set, the local docker
+// environment when there is no context file yet
+pub fn get_current_context() -> Result<Environment> {
+    let filename = tembo_context_file_path();
+
+    match fs::read_to_string(&filename) {
+        Ok(contents) => current_environment(&contents, &filename),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(local_environment()),
+        Err(e) => Err(e)
+            .with_context(|| format!("Couldn't read context file {}, try `tembo init`", filename)),
+    }
+}
+
+// the environment of the default context file, used until `tembo init` writes one
+fn local_environment() -> Environment {
+    Environment {
+        name: String::from("local"),
+        target: TARGET_DOCKER.to_string(),
+        org_id: None,
+        profile: None,
+        set: Some(true),
+        runtime: None,
+    }
+}
+
+fn current_environment(contents: &str, filename: &str) -> Result<Environment> {
+    let data: Context = toml::from_str(contents)
+        .with_context(|| format!("Unable to load context file {}", filename))?;
+
+    match data.environment.into_iter().find(|e| e.set == Some(true)) {
+        Some(environment) => Ok(environment),
+        None => bail!("No context is set, set one using `tembo context set -n <name>`"),
+    }
+}
+
+// returns the credentials profile with the given name
+pub fn get_profile(name: &str) -> Result<Profile> {
+    let filename = tembo_credentials_file_path();
+
+    let contents = fs::read_to_string(&filename)
+        .with_context(|| format!("Couldn't read credentials file {}", filename))?;
+
+    let data: Credential = toml::from_str(&contents)
+        .with_context(|| format!("Unable to load credentials file {}", filename))?;
+
+    match data.environment.into_iter().find(|p| p.name == name) {
+        Some(profile) => Ok(profile),
+        None => bail!(
+            "Profile {} not found in credentials file {}",
+            name,
+            filename
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_environment_test() {
+        let local = current_environment(CONTEXT_DEFAULT_TEXT, "context").unwrap();
+        assert_eq!(local, local_environment());
+
+        let cloud = CONTEXT_DEFAULT_TEXT
+            .replace("set = true", "set = false")
+            .replace("profile = 'prod'", "profile = 'prod'\nset = true");
+        assert_eq!(
+            current_environment(&cloud, "context").unwrap().target,
+            TARGET_TEMBO_CLOUD
+        );
+
+        let unset = CONTEXT_DEFAULT_TEXT.replace("set = true", "set = false");
+        assert!(current_environment(&unset, "context").is_err());
+    }
 }
@@ -0,0 +1,651 @@
+//! A container runtime talking to the Docker Engine API over its unix socket
+//! (https://docs.docker.com/engine/api/), without shelling out to the docker CLI
+
+use crate::cli::container_runtime::{
+    binary_on_path, ContainerRuntime, ExecOutput, RunOptions, RuntimeError, RuntimeResult,
+};
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::Command;
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+const API_VERSION: &str = "v1.41";
+
+pub struct DockerEngine {
+    // the name shown in messages and the client binary looked up when the socket is unreachable
+    pub runtime_name: String,
+    pub socket_path: String,
+}
+
+// a response from the engine, with a chunked body already decoded
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(&self) -> RuntimeResult<Value> {
+        serde_json::from_slice(&self.body).map_err(|e| RuntimeError::Api {
+            status: self.status,
+            message: format!("invalid response: {}", e),
+        })
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+}
+
+impl DockerEngine {
+    // uses the host in DOCKER_HOST, then the one of the active docker context, then the default
+    // socket, failing when the host isn't a unix socket
+    pub fn from_env() -> RuntimeResult<DockerEngine> {
+        let host = env::var("DOCKER_HOST")
+            .ok()
+            .filter(|host| !host.is_empty())
+            .or_else(context_host);
+
+        Ok(DockerEngine {
+            runtime_name: String::from("Docker"),
+            socket_path: socket_path(host.as_deref())?,
+        })
+    }
+
+    fn connection_error(&self) -> RuntimeError {
+        let binary = self.runtime_name.to_lowercase();
+
+        if binary_on_path(&binary) {
+            RuntimeError::NotRunning(self.runtime_name.clone())
+        } else {
+            RuntimeError::NotInstalled(self.runtime_name.clone())
+        }
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> RuntimeResult<Response> {
+        let mut stream =
+            UnixStream::connect(&self.socket_path).map_err(|_| self.connection_error())?;
+
+        let head = format!(
+            "{} /{}{} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            method,
+            API_VERSION,
+            path,
+            content_type,
+            body.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+
+        let mut raw = vec![];
+        stream.read_to_end(&mut raw)?;
+
+        parse_response(&raw)
+    }
+
+    fn get(&self, path: &str) -> RuntimeResult<Response> {
+        self.request("GET", path, "application/json", &[])
+    }
+
+    fn post(&self, path: &str, body: &Value) -> RuntimeResult<Response> {
+        self.request(
+            "POST",
+            path,
+            "application/json",
+            body.to_string().as_bytes(),
+        )
+    }
+
+    fn delete(&self, path: &str) -> RuntimeResult<Response> {
+        self.request("DELETE", path, "application/json", &[])
+    }
+
+    // fails on any status other than the ones expected
+    fn expect(&self, response: Response, expected: &[u16]) -> RuntimeResult<Response> {
+        if expected.contains(&response.status) {
+            return Ok(response);
+        }
+
+        let message = match response.json() {
+            Ok(json) => json["message"]
+                .as_str()
+                .map(|m| m.to_string())
+                .unwrap_or_else(|| response.text()),
+            Err(_) => response.text(),
+        };
+
+        Err(RuntimeError::Api {
+            status: response.status,
+            message,
+        })
+    }
+
+    fn network_exists(&self, network: &str) -> RuntimeResult<bool> {
+        let filters = json!({ "name": [format!("^{}$", network)] }).to_string();
+        let response = self.get(&format!("/networks?filters={}", url_encode(&filters)))?;
+        let json = self.expect(response, &[200])?.json()?;
+
+        Ok(json.as_array().map(|a| !a.is_empty()).unwrap_or(false))
+    }
+}
+
+// the host of the active docker context, e.g. Docker Desktop's or colima's socket
+fn context_host() -> Option<String> {
+    if !binary_on_path("docker") {
+        return None;
+    }
+
+    let output = Command::new("docker")
+        .args([
+            "context",
+            "inspect",
+            "--format",
+            "{{.Endpoints.docker.Host}}",
+        ])
+        .output()
+        .ok()?;
+    let host = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    (output.status.success() && !host.is_empty()).then_some(host)
+}
+
+fn socket_path(host: Option<&str>) -> RuntimeResult<String> {
+    match host {
+        None => Ok(DEFAULT_SOCKET.to_string()),
+        Some(host) => match host.strip_prefix("unix://") {
+            Some(path) => Ok(path.to_string()),
+            None => Err(RuntimeError::Unsupported(format!(
+                "Docker host {} isn't a unix socket, tembo can only reach a local Docker Engine; \
+                 set DOCKER_HOST or the docker context to a unix:// socket",
+                host
+            ))),
+        },
+    }
+}
+
+impl ContainerRuntime for DockerEngine {
+    fn name(&self) -> String {
+        self.runtime_name.clone()
+    }
+
+    fn ping(&self) -> RuntimeResult {
+        let response = self.get("/_ping")?;
+        self.expect(response, &[200])?;
+
+        Ok(())
+    }
+
+    fn container_id(&self, name: &str) -> RuntimeResult<Option<String>> {
+        let filters = json!({ "name": [format!("^/?{}$", name)] }).to_string();
+        let response = self.get(&format!(
+            "/containers/json?all=true&filters={}",
+            url_encode(&filters)
+        ))?;
+        let json = self.expect(response, &[200])?.json()?;
+
+        Ok(json
+            .as_array()
+            .and_then(|containers| containers.first())
+            .and_then(|c| c["Id"].as_str())
+            .map(|id| id.to_string()))
+    }
+
+    fn build_image(&self, context_dir: &str, tag: &str) -> RuntimeResult {
+        let context = tar_directory(Path::new(context_dir))?;
+        let response = self.request(
+            "POST",
+            &format!("/build?t={}&rm=true", url_encode(tag)),
+            "application/x-tar",
+            &context,
+        )?;
+        let response = self.expect(response, &[200])?;
+
+        // the build streams json messages, a failed step is reported as an error message
+        for line in response.text().lines() {
+            if let Ok(message) = serde_json::from_str::<Value>(line) {
+                if let Some(error) = message["error"].as_str() {
+                    return Err(RuntimeError::Api {
+                        status: response.status,
+                        message: error.trim().to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run(&self, options: &RunOptions) -> RuntimeResult<String> {
+        let mut exposed_ports = serde_json::Map::new();
+        let mut port_bindings = serde_json::Map::new();
+
+        for (host_port, container_port) in &options.ports {
+            let key = format!("{}/tcp", container_port);
+            exposed_ports.insert(key.clone(), json!({}));
+            port_bindings.insert(key, json!([{ "HostPort": host_port.to_string() }]));
+        }
+
+        let mut host_config = json!({ "PortBindings": port_bindings });
+
+        if let Some(cpus) = options.cpus {
+            host_config["NanoCpus"] = json!((cpus * 1_000_000_000.0) as i64);
+        }
+        if let Some(memory) = options.memory_bytes {
+            host_config["Memory"] = json!(memory);
+            host_config["MemorySwap"] = json!(memory);
+        }
+        if let Some(storage) = options.storage_bytes {
+            host_config["StorageOpt"] = json!({ "size": storage.to_string() });
+        }
+        if let Some(network) = &options.network {
+            host_config["NetworkMode"] = json!(network);
+        }
+        if !options.volumes.is_empty() {
+            let binds: Vec<String> = options
+                .volumes
+                .iter()
+                .map(|(source, target)| format!("{}:{}", source, target))
+                .collect();
+            host_config["Binds"] = json!(binds);
+        }
+
+        let mut body = json!({
+            "Image": options.image,
+            "Env": options.env,
+            "ExposedPorts": exposed_ports,
+            "HostConfig": host_config,
+        });
+
+        if let Some(entrypoint) = &options.entrypoint {
+            body["Entrypoint"] = json!(entrypoint);
+        }
+        if let Some(cmd) = &options.cmd {
+            body["Cmd"] = json!(cmd);
+        }
+
+        let response = self.post(
+            &format!("/containers/create?name={}", url_encode(&options.name)),
+            &body,
+        )?;
+        let json = self.expect(response, &[201])?.json()?;
+        let id = json["Id"].as_str().unwrap_or_default().to_string();
+
+        let response = self.post(&format!("/containers/{}/start", id), &json!({}))?;
+        self.expect(response, &[204, 304])?;
+
+        Ok(id)
+    }
+
+    fn start(&self, name: &str) -> RuntimeResult {
+        let response = self.post(&format!("/containers/{}/start", name), &json!({}))?;
+        self.expect(response, &[204, 304])?;
+
+        Ok(())
+    }
+
+    fn stop(&self, name: &str) -> RuntimeResult {
+        let response = self.post(&format!("/containers/{}/stop", name), &json!({}))?;
+        self.expect(response, &[204, 304])?;
+
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> RuntimeResult {
+        let response = self.delete(&format!("/containers/{}", name))?;
+        self.expect(response, &[204])?;
+
+        Ok(())
+    }
+
+    fn rename(&self, name: &str, new_name: &str) -> RuntimeResult {
+        let response = self.post(
+            &format!("/containers/{}/rename?name={}", name, url_encode(new_name)),
+            &json!({}),
+        )?;
+        self.expect(response, &[204])?;
+
+        Ok(())
+    }
+
+    fn exec(&self, name: &str, cmd: &[String]) -> RuntimeResult<ExecOutput> {
+        let response = self.post(
+            &format!("/containers/{}/exec", name),
+            &json!({ "AttachStdout": true, "AttachStderr": true, "Cmd": cmd }),
+        )?;
+        let json = self.expect(response, &[201])?.json()?;
+        let exec_id = json["Id"].as_str().unwrap_or_default().to_string();
+
+        let response = self.post(
+            &format!("/exec/{}/start", exec_id),
+            &json!({ "Detach": false, "Tty": false }),
+        )?;
+        let response = self.expect(response, &[200])?;
+        let (stdout, stderr) = demultiplex(&response.body);
+
+        let response = self.get(&format!("/exec/{}/json", exec_id))?;
+        let json = self.expect(response, &[200])?.json()?;
+
+        Ok(ExecOutput {
+            exit_code: json["ExitCode"].as_i64().unwrap_or(-1),
+            stdout,
+            stderr,
+        })
+    }
+
+    fn update_resources(&self, name: &str, cpus: f64, memory_bytes: u64) -> RuntimeResult {
+        let response = self.post(
+            &format!("/containers/{}/update", name),
+            &json!({
+                "NanoCpus": (cpus * 1_000_000_000.0) as i64,
+                "Memory": memory_bytes,
+                "MemorySwap": memory_bytes,
+            }),
+        )?;
+        self.expect(response, &[200])?;
+
+        Ok(())
+    }
+
+    fn supports_storage_limit(&self) -> bool {
+        let info = match self.get("/info").and_then(|r| r.json()) {
+            Ok(info) => info,
+            Err(_) => return false,
+        };
+
+        let backing_fs_is_xfs = info["DriverStatus"]
+            .as_array()
+            .map(|status| {
+                status.iter().any(|pair| {
+                    pair[0].as_str() == Some("Backing Filesystem")
+                        && pair[1].as_str() == Some("xfs")
+                })
+            })
+            .unwrap_or(false);
+
+        match info["Driver"].as_str() {
+            Some("btrfs") | Some("zfs") | Some("devicemapper") => true,
+            // xfs also needs the pquota mount option, which /info doesn't report, so build_run
+            // falls back to no limit when the daemon rejects it
+            Some("overlay2") => backing_fs_is_xfs,
+            _ => false,
+        }
+    }
+
+    fn create_network(&self, network: &str) -> RuntimeResult {
+        if self.network_exists(network)? {
+            return Ok(());
+        }
+
+        let response = self.post("/networks/create", &json!({ "Name": network }))?;
+        self.expect(response, &[201])?;
+
+        Ok(())
+    }
+
+    fn connect_network(&self, network: &str, name: &str) -> RuntimeResult {
+        let response = self.get(&format!("/containers/{}/json", name))?;
+        let json = self.expect(response, &[200])?.json()?;
+
+        if json["NetworkSettings"]["Networks"].get(network).is_some() {
+            return Ok(());
+        }
+
+        let response = self.post(
+            &format!("/networks/{}/connect", network),
+            &json!({ "Container": name }),
+        )?;
+        self.expect(response, &[200])?;
+
+        Ok(())
+    }
+
+    fn remove_volume(&self, name: &str) -> RuntimeResult {
+        let response = self.delete(&format!("/volumes/{}", name))?;
+        self.expect(response, &[204, 404])?;
+
+        Ok(())
+    }
+
+    fn remove_network(&self, network: &str) -> RuntimeResult {
+        if !self.network_exists(network)? {
+            return Ok(());
+        }
+
+        let response = self.delete(&format!("/networks/{}", network))?;
+        self.expect(response, &[204])?;
+
+        Ok(())
+    }
+}
+
+fn parse_response(raw: &[u8]) -> RuntimeResult<Response> {
+    let invalid = |message: &str| RuntimeError::Api {
+        status: 0,
+        message: message.to_string(),
+    };
+
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid("incomplete response"))?;
+    let head = String::from_utf8_lossy(&raw[..split]).to_string();
+    let body = &raw[split + 4..];
+
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| invalid("invalid status line"))?;
+
+    let chunked = head.lines().any(|line| {
+        let line = line.to_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+
+    let body = if chunked {
+        dechunk(body)
+    } else {
+        body.to_vec()
+    };
+
+    Ok(Response { status, body })
+}
+
+// decodes a body sent with chunked transfer encoding
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut decoded = vec![];
+
+    while let Some(line_end) = body.windows(2).position(|w| w == b"\r\n") {
+        let size_line = String::from_utf8_lossy(&body[..line_end]).to_string();
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16)
+            .unwrap_or(0);
+
+        if size == 0 {
+            break;
+        }
+
+        let start = line_end + 2;
+        let end = (start + size).min(body.len());
+        decoded.extend_from_slice(&body[start..end]);
+
+        body = &body[(end + 2).min(body.len())..];
+    }
+
+    decoded
+}
+
+// splits the multiplexed stdout/stderr stream of an exec, each frame having an 8 byte header
+fn demultiplex(mut stream: &[u8]) -> (String, String) {
+    let mut stdout = vec![];
+    let mut stderr = vec![];
+
+    while stream.len() >= 8 {
+        let size = u32::from_be_bytes([stream[4], stream[5], stream[6], stream[7]]) as usize;
+        let end = (8 + size).min(stream.len());
+        let frame = &stream[8..end];
+
+        match stream[0] {
+            2 => stderr.extend_from_slice(frame),
+            _ => stdout.extend_from_slice(frame),
+        }
+
+        stream = &stream[end..];
+    }
+
+    (
+        String::from_utf8_lossy(&stdout).to_string(),
+        String::from_utf8_lossy(&stderr).to_string(),
+    )
+}
+
+// builds an uncompressed tar archive of the files in a directory, used as the build context
+fn tar_directory(dir: &Path) -> RuntimeResult<Vec<u8>> {
+    let mut archive = vec![];
+    append_directory(&mut archive, dir, "")?;
+
+    // an archive ends with two empty blocks
+    archive.extend_from_slice(&[0; 1024]);
+
+    Ok(archive)
+}
+
+fn append_directory(archive: &mut Vec<u8>, dir: &Path, prefix: &str) -> RuntimeResult {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+
+        if path.is_dir() {
+            append_header(archive, &format!("{}/", name), 0, b'5')?;
+            append_directory(archive, &path, &format!("{}/", name))?;
+        } else {
+            let contents = fs::read(&path)?;
+            append_header(archive, &name, contents.len(), b'0')?;
+            archive.extend_from_slice(&contents);
+
+            let padding = (512 - contents.len() % 512) % 512;
+            archive.resize(archive.len() + padding, 0);
+        }
+    }
+
+    Ok(())
+}
+
+fn append_header(archive: &mut Vec<u8>, name: &str, size: usize, kind: u8) -> RuntimeResult {
+    if name.len() > 100 {
+        return Err(RuntimeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("path too long for build context: {}", name),
+        )));
+    }
+
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+
+    let mode = if kind == b'5' { "0000755" } else { "0000644" };
+    header[100..107].copy_from_slice(mode.as_bytes());
+    header[108..115].copy_from_slice(b"0000000");
+    header[116..123].copy_from_slice(b"0000000");
+    header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+    header[136..147].copy_from_slice(b"00000000000");
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // the checksum is computed with its own field filled with spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+    archive.extend_from_slice(&header);
+
+    Ok(())
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_path_test() {
+        assert_eq!(socket_path(None).unwrap(), DEFAULT_SOCKET);
+        assert_eq!(
+            socket_path(Some("unix:///Users/me/.colima/default/docker.sock")).unwrap(),
+            "/Users/me/.colima/default/docker.sock"
+        );
+        assert!(socket_path(Some("tcp://10.0.0.5:2376"))
+            .unwrap_err()
+            .to_string()
+            .contains("tcp://10.0.0.5:2376 isn't a unix socket"));
+    }
+
+    #[test]
+    fn parse_response_test() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let response = parse_response(raw).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text(), "hello world");
+
+        let raw = b"HTTP/1.1 404 Not Found\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(parse_response(raw).unwrap().status, 404);
+    }
+
+    #[test]
+    fn demultiplex_test() {
+        let mut stream = vec![1, 0, 0, 0, 0, 0, 0, 3];
+        stream.extend_from_slice(b"out");
+        stream.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 3]);
+        stream.extend_from_slice(b"err");
+
+        assert_eq!(
+            demultiplex(&stream),
+            (String::from("out"), String::from("err"))
+        );
+    }
+
+    #[test]
+    fn tar_directory_test() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM scratch\n").unwrap();
+
+        let archive = tar_directory(dir.path()).unwrap();
+
+        assert_eq!(archive.len() % 512, 0);
+        assert_eq!(&archive[..10], b"Dockerfile");
+        assert_eq!(&archive[257..262], b"ustar");
+        assert_eq!(&archive[512..525], b"FROM scratch\n");
+    }
+
+    #[test]
+    fn url_encode_test() {
+        assert_eq!(
+            url_encode("{\"name\":[\"^a$\"]}"),
+            "%7B%22name%22%3A%5B%22%5Ea%24%22%5D%7D"
+        );
+    }
+}
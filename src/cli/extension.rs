@@ -1,8 +1,15 @@
 // Extensions are defined in Tembo Stacks and can be custom installed by users
 // they are extensions to Postgres
 
-use crate::{Deserialize, Serialize};
+use crate::cli::container_runtime::{exec_checked, ContainerRuntime};
+use crate::cli::docker::{quote_identifier, quote_literal, Docker};
+use crate::cli::tembo_config::DEFAULT_DATABASE;
+use crate::{Deserialize, Result, Serialize};
 use chrono::{DateTime, Utc};
+use simplelog::*;
+
+// the versions extensions were updated from and to, kept in the default database
+const UPGRADES_TABLE: &str = "_tembo_extension_upgrades";
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Extension {
@@ -18,3 +25,109 @@ pub struct ExtensionLocation {
     pub enabled: String,
     pub version: String,
 }
+
+// the version of the extension created in the database, None when it isn't created there
+pub fn installed_version(
+    runtime: &dyn ContainerRuntime,
+    instance_name: &str,
+    database: &str,
+    name: &str,
+) -> Result<Option<String>> {
+    let version = Docker::psql_query_database(
+        runtime,
+        instance_name,
+        database,
+        &format!(
+            "select extversion from pg_extension where extname = {}",
+            quote_literal(name)
+        ),
+    )?;
+    let version = version.trim();
+
+    Ok((!version.is_empty()).then(|| version.to_string()))
+}
+
+// installs the Trunk project in the container, the latest version when none is given
+pub fn trunk_install(
+    runtime: &dyn ContainerRuntime,
+    container: &str,
+    project: &str,
+    version: Option<&str>,
+    pg_version: u8,
+) -> Result<()> {
+    let pg_version = pg_version.to_string();
+    let mut cmd = vec!["trunk", "install", "--pg-version", &pg_version];
+    if let Some(version) = version {
+        cmd.extend(["--version", version]);
+    }
+    cmd.push(project);
+
+    exec_checked(runtime, container, &cmd)?;
+
+    Ok(())
+}
+
+// determines if the extension's control file is in the container's extension directory, which is
+// where a successful trunk install leaves it
+pub fn control_file_exists(
+    runtime: &dyn ContainerRuntime,
+    container: &str,
+    name: &str,
+) -> Result<bool> {
+    let output = runtime.exec(
+        container,
+        &[
+            String::from("sh"),
+            String::from("-c"),
+            String::from("test -f \"$(pg_config --sharedir)/extension/$1.control\""),
+            String::from("sh"),
+            name.to_string(),
+        ],
+    )?;
+
+    Ok(output.exit_code == 0)
+}
+
+// updates the extension in the database and records the versions before and after
+pub fn update(
+    runtime: &dyn ContainerRuntime,
+    instance_name: &str,
+    database: &str,
+    name: &str,
+    from: &str,
+    to: &str,
+) -> Result<()> {
+    Docker::psql_query_database(
+        runtime,
+        instance_name,
+        database,
+        &format!(
+            "alter extension {} update to {}",
+            quote_identifier(name),
+            quote_literal(to)
+        ),
+    )?;
+
+    Docker::psql_query_database(
+        runtime,
+        instance_name,
+        DEFAULT_DATABASE,
+        &format!(
+            "create table if not exists {table} (name text not null, database text not null, \
+             from_version text not null, to_version text not null, upgraded_at timestamptz not null default now()); \
+             insert into {table} (name, database, from_version, to_version) values ({}, {}, {}, {})",
+            quote_literal(name),
+            quote_literal(database),
+            quote_literal(from),
+            quote_literal(to),
+            table = UPGRADES_TABLE,
+        ),
+    )?;
+
+    info!(
+        "- Extension {} upgraded from {} to {} in {} on {}",
+        name, from, to, database, instance_name
+    );
+
+    Ok(())
+}
@@ -2,12 +2,16 @@
 //! (a local container that runs with certain attributes and properties)
 
 use crate::cli::config::Config;
+use crate::cli::container_runtime::{ContainerRuntime, RunOptions};
 use crate::cli::database::Database;
-use crate::cli::extension::Extension;
+use crate::cli::docker::{quote_identifier, volume_name, Docker, DATA_PATH};
+use crate::cli::extension::{self, Extension};
+use crate::cli::resources;
 use crate::cli::stacks;
 use crate::cli::stacks::{Stack, TrunkInstall};
+use crate::cli::tembo_config::{base_image, DEFAULT_PG_VERSION};
 use crate::Result;
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use chrono::prelude::*;
 use clap::ArgMatches;
 use serde::Deserialize;
@@ -15,7 +19,6 @@ use serde::Serialize;
 use simplelog::*;
 use spinners::{Spinner, Spinners};
 use std::cmp::PartialEq;
-use std::process::Command as ShellCommand;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Instance {
@@ -23,6 +26,10 @@ pub struct Instance {
     pub r#type: Option<String>,
     pub port: Option<String>, // TODO: persist as an <u16>
     pub version: Option<String>,
+    // the major Postgres version, instances created before it was recorded run the default
+    pub pg_version: Option<u8>,
+    // the named volume holding the instance's data, kept across rebuilds
+    pub volume: Option<String>,
     pub created_at: Option<DateTime<Utc>>,
     pub installed_extensions: Vec<InstalledExtension>,
     pub enabled_extensions: Vec<EnabledExtension>,
@@ -57,163 +64,193 @@ pub struct InstanceError {
 }
 
 impl Instance {
-    pub fn init(&self) -> Result<()> {
-        let stack = self.stack();
+    pub fn init(&self, runtime: &dyn ContainerRuntime) -> Result<()> {
+        let stack = self.stack()?;
 
-        self.build();
+        self.build(runtime, &stack)?;
 
         for install in &stack.trunk_installs {
-            let _ = self.install_extension(install);
+            if let Err(e) = self.install_extension(runtime, install) {
+                error!("{}", e);
+            }
         }
 
         for extension in &stack.extensions {
-            let _ = self.enable_extension(extension);
+            if let Err(e) = self.enable_extension(runtime, extension) {
+                error!("{}", e);
+            }
         }
 
         Ok(())
     }
 
     // Returns the stack the instance is based on
-    // TODO: determine if there is a way to return a vector element in a better way
-    fn stack(&self) -> Stack {
-        let stacks = stacks::define_stacks();
-        let stack_type = self.r#type.clone().unwrap().to_lowercase();
-
-        let stack_details: Vec<_> = stacks
-            .stacks
-            .into_iter()
-            .filter(|s| s.name.to_lowercase() == stack_type)
-            .collect();
-
-        let stack = stack_details.first().unwrap();
-
-        Stack {
-            name: stack.name.clone(),
-            description: stack.description.clone(),
-            version: stack.version.clone(),
-            trunk_installs: stack.trunk_installs.clone(),
-            extensions: stack.extensions.clone(),
+    fn stack(&self) -> Result<Stack> {
+        let stack_type = self.r#type.clone().unwrap_or_default();
+
+        match stacks::define_stacks()?.find(&stack_type) {
+            Some(stack) => Ok(stack.clone()),
+            None => bail!(
+                "Stack {} of instance {} is not defined",
+                stack_type,
+                self.name.clone().unwrap()
+            ),
         }
     }
 
-    // builds (and starts) a new container
-    fn build(&self) {
-        let port_option = format!(
-            "--publish {}:{}",
-            self.port.clone().unwrap(),
-            self.port.clone().unwrap(),
-        );
+    // builds (and starts) a new container with the stack's resources and Postgres configuration
+    fn build(&self, runtime: &dyn ContainerRuntime, stack: &Stack) -> Result<()> {
+        let port = self.port()?;
+
+        let cpus = stack.cpu.as_deref().map(resources::parse_cpu).transpose()?;
+        let memory_bytes = stack
+            .memory
+            .as_deref()
+            .map(resources::parse_quantity)
+            .transpose()?;
+        let storage_bytes = match stack.storage.as_deref() {
+            Some(storage) if runtime.supports_storage_limit() => {
+                Some(resources::parse_quantity(storage)?)
+            }
+            _ => None,
+        };
+
+        let cmd = (!stack.postgres_config.is_empty()).then(|| {
+            let mut cmd = vec![String::from("postgres")];
+            for config in &stack.postgres_config {
+                cmd.push(String::from("-c"));
+                cmd.push(format!("{}={}", config.name, config.value));
+            }
+            cmd
+        });
+
+        self.starting(|| {
+            runtime.run(&RunOptions {
+                name: self.name.clone().unwrap(),
+                image: base_image(self.pg_version()),
+                ports: vec![(port, port)],
+                cpus,
+                memory_bytes,
+                storage_bytes,
+                volumes: vec![(self.volume(), DATA_PATH.to_string())],
+                cmd,
+                ..RunOptions::default()
+            })?;
+
+            Ok(())
+        })
+    }
+
+    // starts the existing container, stopping it again when its data is of another major version
+    pub fn start(&self, runtime: &dyn ContainerRuntime) -> Result<()> {
+        let name = self.name.clone().unwrap();
 
-        let mut command = String::from("cd tembo ");
-        command.push_str("&& docker run -d --name ");
-        command.push_str(&self.name.clone().unwrap());
-        command.push(' ');
-        command.push_str(&port_option);
-        command.push_str(" tembo-pg");
+        self.starting(|| {
+            runtime.start(&name)?;
 
-        let _ = self.run_command(&command);
+            if let Err(e) = Docker::check_pg_version(runtime, &name, self.pg_version()) {
+                runtime.stop(&name)?;
+                return Err(e);
+            }
+
+            Ok(())
+        })
     }
 
-    // starts the existing container
-    pub fn start(&self) {
-        let mut command = String::from("cd tembo ");
-        command.push_str("&& docker start ");
-        command.push_str(&self.name.clone().unwrap());
+    pub fn pg_version(&self) -> u8 {
+        self.pg_version.unwrap_or(DEFAULT_PG_VERSION)
+    }
 
-        let _ = self.run_command(&command);
+    pub fn volume(&self) -> String {
+        self.volume
+            .clone()
+            .unwrap_or_else(|| volume_name(&self.name.clone().unwrap(), self.pg_version()))
     }
 
-    fn run_command(&self, command: &str) -> Result<()> {
-        let mut sp = Spinner::new(Spinners::Line, "Starting instance".into());
+    fn port(&self) -> Result<u16> {
+        let port = self.port.clone().unwrap_or_default();
 
-        let output = ShellCommand::new("sh")
-            .arg("-c")
-            .arg(command)
-            .output()
-            .expect("failed to execute process");
+        port.parse::<u16>()
+            .map_err(|_| anyhow!("Instance port `{}` is not a valid port", port))
+    }
 
-        let message = format!(
-            "- Tembo instance started on {}",
-            &self.port.clone().unwrap(),
-        );
-        sp.stop_with_message(message);
+    fn starting(&self, start: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut sp = Spinner::new(Spinners::Line, "Starting instance".into());
 
-        let stderr = String::from_utf8(output.stderr).unwrap();
+        let result = start();
+        sp.stop_with_newline();
 
-        if !stderr.is_empty() {
-            bail!("There was an issue starting the instance: {}", stderr)
+        if let Err(e) = result {
+            bail!("There was an issue starting the instance: {}", e)
         }
 
+        info!(
+            "- Tembo instance started on {}",
+            &self.port.clone().unwrap()
+        );
+
         Ok(())
     }
 
-    pub fn install_extension(&self, extension: &TrunkInstall) -> Result<()> {
+    pub fn install_extension(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        extension: &TrunkInstall,
+    ) -> Result<()> {
         let mut sp = Spinner::new(Spinners::Dots12, "Installing extension".into());
 
-        let mut command = String::from("cd tembo && docker exec ");
-        command.push_str(&self.name.clone().unwrap());
-        command.push_str(" sh -c 'trunk install ");
-        command.push_str(&extension.name.clone().unwrap());
-        command.push('\'');
-
-        let output = ShellCommand::new("sh")
-            .arg("-c")
-            .arg(&command)
-            .output()
-            .expect("failed to execute process");
+        let name = extension.name.clone().unwrap();
+        let result = extension::trunk_install(
+            runtime,
+            &self.name.clone().unwrap(),
+            &name,
+            extension.version.as_deref(),
+            self.pg_version(),
+        );
 
         sp.stop_with_newline();
 
-        let stderr = String::from_utf8(output.stderr).unwrap();
-
-        if !stderr.is_empty() {
-            bail!("There was an issue installing the extension: {}", stderr)
-        } else {
-            let mut msg = String::from("- Stack extension installed: ");
-            msg.push_str(&extension.name.clone().unwrap());
+        if let Err(e) = result {
+            bail!("There was an issue installing the extension: {}", e)
+        }
 
-            println!("{}", msg);
+        // NOTE: uses println vs logging intentionally
+        println!("- Stack extension installed: {}", name);
 
-            Ok(())
-        }
+        Ok(())
     }
 
-    fn enable_extension(&self, extension: &Extension) -> Result<()> {
+    // creates the extension in each database it's located in
+    fn enable_extension(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        extension: &Extension,
+    ) -> Result<()> {
         let mut sp = Spinner::new(Spinners::Dots12, "Enabling extension".into());
 
-        let locations = extension
-            .locations
-            .iter()
-            .map(|s| s.database.as_str())
-            .collect::<Vec<&str>>()
-            .join(", ");
-
-        let mut command = String::from("docker exec ");
-        command.push_str(&self.name.clone().unwrap());
-        command.push_str(" sh -c 'psql -U postgres -c create extension if not exists \"");
-        command.push_str(&extension.name.clone().unwrap());
-        command.push_str("\" schema ");
-        command.push_str(&locations);
-        command.push_str(" cascade;'");
-
-        let output = ShellCommand::new("sh")
-            .arg("-c")
-            .arg(&command)
-            .output()
-            .expect("failed to execute process");
-
-        let mut msg = String::from("- Stack extension enabled: ");
-        msg.push_str(&extension.name.clone().unwrap());
-
-        sp.stop_with_message(msg);
-
-        let stderr = String::from_utf8(output.stderr).unwrap();
-
-        if !stderr.is_empty() {
-            bail!("There was an issue enabling the extension: {}", stderr)
-        } else {
-            Ok(())
+        let name = extension.name.clone().unwrap();
+        let query = format!(
+            "create extension if not exists {} cascade",
+            quote_identifier(&name)
+        );
+
+        let result = extension.locations.iter().try_for_each(|location| {
+            Docker::psql_query_database(
+                runtime,
+                &self.name.clone().unwrap(),
+                &location.database,
+                &query,
+            )
+            .map(|_| ())
+        });
+
+        sp.stop_with_message(format!("- Stack extension enabled: {}", name));
+
+        if let Err(e) = result {
+            bail!("There was an issue enabling the extension: {}", e)
         }
+
+        Ok(())
     }
 
     pub fn find(args: &ArgMatches, name: &str) -> Result<Instance> {
@@ -0,0 +1,680 @@
+//! A migration runner for the `<instance>/migrations/` directory of each tembo.toml instance.
+//! Applied versions are tracked with checksums in a table of the instance, each migration runs in
+//! a transaction. History recorded by sqlx, which older versions of tembo ran, is imported
+
+use crate::cli::container_runtime::ContainerRuntime;
+use crate::cli::docker::{quote_literal, Docker};
+use crate::Result;
+use anyhow::{anyhow, bail};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+pub const MIGRATIONS_DIR: &str = "migrations";
+pub const MIGRATIONS_TABLE: &str = "_tembo_migrations";
+const SQLX_MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+
+// older versions of tembo rendered the extensions of tembo.toml into this migration, it's now run
+// by apply on its own and the file left behind is ignored
+const LEGACY_EXTENSIONS_FILE: &str = "1_extensions.sql";
+const LEGACY_EXTENSIONS_VERSION: i64 = 1;
+const LEGACY_EXTENSIONS_DESCRIPTION: &str = "extensions";
+
+const UP_SUFFIX: &str = ".up.sql";
+const DOWN_SUFFIX: &str = ".down.sql";
+const SQL_SUFFIX: &str = ".sql";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub version: i64,
+    pub description: String,
+    pub up: String,
+    pub down: Option<String>,
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub description: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    // applied, but its file was edited since
+    ChecksumMismatch,
+    // applied, but its file no longer exists
+    Missing,
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationState::Applied => f.write_str("applied"),
+            MigrationState::Pending => f.write_str("pending"),
+            MigrationState::ChecksumMismatch => f.write_str("checksum mismatch"),
+            MigrationState::Missing => f.write_str("missing"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+    pub applied_at: Option<String>,
+}
+
+// the migrations directory of an instance
+pub fn dir(instance_name: &str) -> String {
+    format!("{}/{}", instance_name, MIGRATIONS_DIR)
+}
+
+// the migrations of an instance, none when it has no migrations directory yet
+pub fn load_instance(instance_name: &str) -> Result<Vec<Migration>> {
+    let dir = dir(instance_name);
+
+    if !Path::new(&dir).is_dir() {
+        return Ok(vec![]);
+    }
+
+    load(&dir)
+}
+
+// reads `<version>_<description>.sql` and `<version>_<description>.up.sql`/`.down.sql` files
+pub fn load(dir: &str) -> Result<Vec<Migration>> {
+    let mut ups: BTreeMap<i64, (String, String)> = BTreeMap::new();
+    let mut downs: HashMap<i64, String> = HashMap::new();
+
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| anyhow!("Couldn't read migrations directory {}: {}", dir, e))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        if !file_name.ends_with(SQL_SUFFIX) || file_name == LEGACY_EXTENSIONS_FILE {
+            continue;
+        }
+
+        let (stem, is_down) = if let Some(stem) = file_name.strip_suffix(DOWN_SUFFIX) {
+            (stem, true)
+        } else if let Some(stem) = file_name.strip_suffix(UP_SUFFIX) {
+            (stem, false)
+        } else {
+            (file_name.strip_suffix(SQL_SUFFIX).unwrap(), false)
+        };
+
+        let (version, description) = parse_name(stem).ok_or_else(|| {
+            anyhow!(
+                "Invalid migration file name {}, expected <version>_<description>.sql",
+                file_name
+            )
+        })?;
+        let sql = fs::read_to_string(&path)?;
+
+        if is_down {
+            downs.insert(version, sql);
+        } else if ups.insert(version, (description, sql)).is_some() {
+            bail!("Duplicate migration version {} in {}", version, dir);
+        }
+    }
+
+    if let Some(version) = downs.keys().find(|v| !ups.contains_key(v)) {
+        bail!("Migration {} has a down file but no up file", version);
+    }
+
+    Ok(ups
+        .into_iter()
+        .map(|(version, (description, up))| Migration {
+            version,
+            description,
+            checksum: checksum(&up),
+            down: downs.remove(&version),
+            up,
+        })
+        .collect())
+}
+
+fn parse_name(stem: &str) -> Option<(i64, String)> {
+    let (version, description) = stem.split_once('_')?;
+
+    Some((version.parse().ok()?, description.replace('_', " ")))
+}
+
+pub fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// the state of every local and applied migration, ordered by version
+pub fn status(migrations: &[Migration], applied: &[AppliedMigration]) -> Vec<MigrationStatus> {
+    let applied_by_version: HashMap<i64, &AppliedMigration> =
+        applied.iter().map(|a| (a.version, a)).collect();
+
+    let mut statuses: Vec<MigrationStatus> = migrations
+        .iter()
+        .map(|m| {
+            let applied = applied_by_version.get(&m.version);
+            let state = match applied {
+                None => MigrationState::Pending,
+                Some(a) if a.checksum != m.checksum => MigrationState::ChecksumMismatch,
+                Some(_) => MigrationState::Applied,
+            };
+
+            MigrationStatus {
+                version: m.version,
+                description: m.description.clone(),
+                state,
+                applied_at: applied.map(|a| a.applied_at.clone()),
+            }
+        })
+        .collect();
+
+    for a in applied {
+        if !migrations.iter().any(|m| m.version == a.version) {
+            statuses.push(MigrationStatus {
+                version: a.version,
+                description: a.description.clone(),
+                state: MigrationState::Missing,
+                applied_at: Some(a.applied_at.clone()),
+            });
+        }
+    }
+
+    statuses.sort_by_key(|s| s.version);
+    statuses
+}
+
+// the contents of a new migration file, or up and down files when reversible
+pub fn new_migration_files(version: &str, name: &str, reversible: bool) -> Vec<(String, String)> {
+    let stem = format!("{}_{}", version, name.trim().replace([' ', '-'], "_"));
+
+    if reversible {
+        vec![
+            (
+                format!("{}{}", stem, UP_SUFFIX),
+                String::from("-- Add up migration script here\n"),
+            ),
+            (
+                format!("{}{}", stem, DOWN_SUFFIX),
+                String::from("-- Add down migration script here\n"),
+            ),
+        ]
+    } else {
+        vec![(
+            format!("{}{}", stem, SQL_SUFFIX),
+            String::from("-- Add migration script here\n"),
+        )]
+    }
+}
+
+// applies and reverts migrations on a local instance, through psql in its container
+pub struct Migrator<'a> {
+    pub runtime: &'a dyn ContainerRuntime,
+    pub instance_name: String,
+}
+
+impl<'a> Migrator<'a> {
+    pub fn new(runtime: &'a dyn ContainerRuntime, instance_name: &str) -> Migrator<'a> {
+        Migrator {
+            runtime,
+            instance_name: instance_name.to_string(),
+        }
+    }
+
+    fn table_exists(&self, table: &str) -> Result<bool> {
+        let exists = Docker::psql_query(
+            self.runtime,
+            &self.instance_name,
+            &format!("select to_regclass({}) is not null", quote_literal(table)),
+        )?;
+
+        Ok(exists.trim() == "t")
+    }
+
+    // the migrations recorded in the instance, none when the table hasn't been created yet
+    pub fn applied(&self, migrations: &[Migration]) -> Result<Vec<AppliedMigration>> {
+        if !self.table_exists(MIGRATIONS_TABLE)? {
+            return self.sqlx_applied(migrations);
+        }
+
+        // description goes last, it may contain the field separator
+        let rows = Docker::psql_query(
+            self.runtime,
+            &self.instance_name,
+            &format!(
+                "select version, checksum, applied_at, description from {} order by version",
+                MIGRATIONS_TABLE
+            ),
+        )?;
+
+        parse_applied(&rows, MIGRATIONS_TABLE)
+    }
+
+    // the history sqlx recorded, checksummed like the local files since sqlx uses another hash,
+    // which is imported when the migrations table is created
+    fn sqlx_applied(&self, migrations: &[Migration]) -> Result<Vec<AppliedMigration>> {
+        if !self.table_exists(SQLX_MIGRATIONS_TABLE)? {
+            return Ok(vec![]);
+        }
+
+        let rows = Docker::psql_query(
+            self.runtime,
+            &self.instance_name,
+            &format!(
+                "select version, '', installed_on, description from {} where success order by version",
+                SQLX_MIGRATIONS_TABLE
+            ),
+        )?;
+
+        Ok(parse_applied(&rows, SQLX_MIGRATIONS_TABLE)?
+            .into_iter()
+            .filter(|a| {
+                !(a.version == LEGACY_EXTENSIONS_VERSION
+                    && a.description == LEGACY_EXTENSIONS_DESCRIPTION)
+            })
+            .map(|a| AppliedMigration {
+                checksum: migrations
+                    .iter()
+                    .find(|m| m.version == a.version)
+                    .map(|m| m.checksum.clone())
+                    .unwrap_or_default(),
+                ..a
+            })
+            .collect())
+    }
+
+    pub fn status(&self, migrations: &[Migration]) -> Result<Vec<MigrationStatus>> {
+        Ok(status(migrations, &self.applied(migrations)?))
+    }
+
+    // creates the migrations table, with the history imported from sqlx if there is any
+    fn create_table(&self, applied: &[AppliedMigration]) -> Result<()> {
+        if self.table_exists(MIGRATIONS_TABLE)? {
+            return Ok(());
+        }
+
+        let create = format!(
+            "create table {} (\
+             version bigint primary key, \
+             description text not null, \
+             checksum text not null, \
+             applied_at timestamptz not null default now())",
+            MIGRATIONS_TABLE
+        );
+        let mut statements = vec![create];
+
+        for a in applied {
+            statements.push(format!(
+                "insert into {} (version, description, checksum, applied_at) values ({}, {}, {}, {})",
+                MIGRATIONS_TABLE,
+                a.version,
+                quote_literal(&a.description),
+                quote_literal(&a.checksum),
+                quote_literal(&a.applied_at)
+            ));
+        }
+
+        let statements: Vec<&str> = statements.iter().map(|s| s.as_str()).collect();
+        self.run_in_transaction(&statements)
+    }
+
+    // applies pending migrations up to and including the target version, returning them
+    pub fn up(&self, migrations: &[Migration], target: Option<i64>) -> Result<Vec<i64>> {
+        let applied = self.applied(migrations)?;
+        let statuses = status(migrations, &applied);
+
+        if let Some(s) = statuses
+            .iter()
+            .find(|s| s.state == MigrationState::ChecksumMismatch)
+        {
+            bail!(
+                "Migration {} ({}) was changed after it was applied, revert it before editing",
+                s.version,
+                s.description
+            );
+        }
+
+        self.create_table(&applied)?;
+
+        let mut applied = vec![];
+
+        for migration in migrations {
+            let pending = statuses
+                .iter()
+                .any(|s| s.version == migration.version && s.state == MigrationState::Pending);
+
+            if !pending || target.map(|t| migration.version > t).unwrap_or(false) {
+                continue;
+            }
+
+            let record = format!(
+                "insert into {} (version, description, checksum) values ({}, {}, {})",
+                MIGRATIONS_TABLE,
+                migration.version,
+                quote_literal(&migration.description),
+                quote_literal(&migration.checksum)
+            );
+
+            self.run_in_transaction(&[&migration.up, &record])
+                .map_err(|e| anyhow!("Migration {} failed: {}", migration.version, e))?;
+
+            applied.push(migration.version);
+        }
+
+        Ok(applied)
+    }
+
+    // reverts the last applied migrations with their down files, returning them
+    pub fn down(&self, migrations: &[Migration], steps: usize) -> Result<Vec<i64>> {
+        let mut applied = self.applied(migrations)?;
+        self.create_table(&applied)?;
+        applied.reverse();
+
+        let mut reverted = vec![];
+
+        for a in applied.iter().take(steps) {
+            let migration = migrations
+                .iter()
+                .find(|m| m.version == a.version)
+                .ok_or_else(|| {
+                    anyhow!("Migration {} is applied but its file is missing", a.version)
+                })?;
+            if a.checksum != migration.checksum {
+                bail!(
+                    "Migration {} ({}) was changed after it was applied, its down file may not revert what was applied",
+                    migration.version,
+                    migration.description
+                );
+            }
+            let down = migration.down.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "Migration {} has no down file, it can't be reverted",
+                    a.version
+                )
+            })?;
+
+            let record = format!(
+                "delete from {} where version = {}",
+                MIGRATIONS_TABLE, migration.version
+            );
+
+            self.run_in_transaction(&[down, &record])
+                .map_err(|e| anyhow!("Reverting migration {} failed: {}", migration.version, e))?;
+
+            reverted.push(migration.version);
+        }
+
+        Ok(reverted)
+    }
+
+    // runs sql that is safe to repeat, such as the migrations rendered by apply
+    pub fn run(&self, sql: &str) -> Result<()> {
+        self.run_in_transaction(&[sql])
+    }
+
+    // runs statements in a single transaction, rolled back if any of them fails. They're run as
+    // a file, as psql -c sends a string as one query and ignores its psql meta-commands
+    fn run_in_transaction(&self, statements: &[&str]) -> Result<()> {
+        Docker::psql_script(self.runtime, &self.instance_name, &script(statements))
+    }
+}
+
+// joins statements into one script, each ended on its own line in case a file ends without a
+// semicolon or with a comment
+fn script(statements: &[&str]) -> String {
+    statements.iter().map(|s| format!("{}\n;\n", s)).collect()
+}
+
+fn parse_applied(rows: &str, table: &str) -> Result<Vec<AppliedMigration>> {
+    rows.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.splitn(4, '|').collect();
+
+            match fields.as_slice() {
+                [version, checksum, applied_at, description] => Ok(AppliedMigration {
+                    version: version.parse()?,
+                    description: description.to_string(),
+                    checksum: checksum.to_string(),
+                    applied_at: applied_at.to_string(),
+                }),
+                _ => bail!("Unexpected row in {}: {}", table, line),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::container_runtime::{ExecOutput, MockContainerRuntime};
+
+    fn migration(version: i64, up: &str) -> Migration {
+        Migration {
+            version,
+            description: format!("migration {}", version),
+            up: up.to_string(),
+            down: None,
+            checksum: checksum(up),
+        }
+    }
+
+    fn applied(version: i64, up: &str) -> AppliedMigration {
+        AppliedMigration {
+            version,
+            description: format!("migration {}", version),
+            checksum: checksum(up),
+            applied_at: String::from("2023-09-01 00:00:00+00"),
+        }
+    }
+
+    #[test]
+    fn load_test() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path();
+
+        fs::write(dir.join("1_create_users.sql"), "create table users ();").unwrap();
+        fs::write(
+            dir.join("2_add_email.up.sql"),
+            "alter table users add email text;",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("2_add_email.down.sql"),
+            "alter table users drop email;",
+        )
+        .unwrap();
+        fs::write(dir.join("README.md"), "not a migration").unwrap();
+
+        let migrations = load(dir.to_str().unwrap()).unwrap();
+        assert_eq!(migrations.len(), 2);
+        assert_eq!(migrations[0].description, "create users");
+        assert_eq!(migrations[0].down, None);
+        assert_eq!(
+            migrations[1].down.as_deref(),
+            Some("alter table users drop email;")
+        );
+
+        fs::write(dir.join("1_duplicate.sql"), "select 1;").unwrap();
+        assert!(load(dir.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn status_test() {
+        let migrations = vec![
+            migration(1, "create table a ();"),
+            migration(2, "create table b ();"),
+            migration(3, "create table c ();"),
+        ];
+        let applied = vec![
+            applied(1, "create table a ();"),
+            applied(2, "create table bb ();"),
+            applied(4, "create table d ();"),
+        ];
+
+        let states: Vec<(i64, MigrationState)> = status(&migrations, &applied)
+            .into_iter()
+            .map(|s| (s.version, s.state))
+            .collect();
+
+        assert_eq!(
+            states,
+            vec![
+                (1, MigrationState::Applied),
+                (2, MigrationState::ChecksumMismatch),
+                (3, MigrationState::Pending),
+                (4, MigrationState::Missing),
+            ]
+        );
+    }
+
+    #[test]
+    fn up_test() {
+        let mut runtime = MockContainerRuntime::new();
+
+        runtime.expect_exec().returning(|_, cmd| {
+            let sql = cmd.last().unwrap();
+
+            let stdout = if sql.contains("to_regclass") {
+                "t\n"
+            } else if sql.starts_with("select version") {
+                "1|8c4b7e4c|2023-09-01 00:00:00+00|create a\n"
+            } else {
+                ""
+            };
+
+            Ok(ExecOutput {
+                exit_code: 0,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            })
+        });
+
+        let mut first = migration(1, "create table a ();");
+        first.checksum = String::from("8c4b7e4c");
+        let migrations = vec![
+            first,
+            migration(2, "create table b ();"),
+            migration(3, "create table c ();"),
+        ];
+
+        let migrator = Migrator::new(&runtime, "app");
+        assert_eq!(migrator.up(&migrations, Some(2)).unwrap(), vec![2]);
+        assert_eq!(migrator.up(&migrations, None).unwrap(), vec![2, 3]);
+        assert!(migrator.down(&migrations, 1).is_err());
+    }
+
+    #[test]
+    fn down_test() {
+        let mut runtime = MockContainerRuntime::new();
+
+        runtime.expect_exec().returning(|_, cmd| {
+            let sql = cmd.last().unwrap();
+
+            let stdout = if sql.contains("to_regclass") {
+                "t\n"
+            } else if sql.starts_with("select version") {
+                "1|8c4b7e4c|2023-09-01 00:00:00+00|create a\n"
+            } else {
+                // reverts run from a file, not from the command line
+                assert!(cmd[0] != "psql" || !sql.contains("drop table"));
+                ""
+            };
+
+            Ok(ExecOutput {
+                exit_code: 0,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            })
+        });
+
+        let mut changed = migration(1, "create table aa ();");
+        changed.down = Some(String::from("drop table a;"));
+        let error = Migrator::new(&runtime, "app")
+            .down(&[changed.clone()], 1)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("was changed after it was applied"));
+
+        changed.checksum = String::from("8c4b7e4c");
+        assert_eq!(
+            Migrator::new(&runtime, "app").down(&[changed], 1).unwrap(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn script_test() {
+        assert_eq!(
+            script(&["create table a ()\n-- done", "select 1;"]),
+            "create table a ()\n-- done\n;\nselect 1;\n;\n"
+        );
+    }
+
+    #[test]
+    fn sqlx_import_test() {
+        let mut runtime = MockContainerRuntime::new();
+
+        runtime.expect_exec().returning(|_, cmd| {
+            let sql = cmd.last().unwrap();
+
+            let stdout = if sql.contains("to_regclass('_sqlx_migrations')") {
+                "t\n"
+            } else if sql.contains("to_regclass") {
+                "f\n"
+            } else if sql.contains("from _sqlx_migrations") {
+                "1||2023-09-01 00:00:00+00|extensions\n2||2023-09-02 00:00:00+00|create b\n"
+            } else {
+                // the legacy extensions migration isn't imported
+                if cmd.iter().any(|c| c.starts_with("create table")) {
+                    assert!(cmd.iter().any(|c| c.contains("values (2, 'create b'")));
+                    assert!(!cmd.iter().any(|c| c.contains("values (1,")));
+                }
+                ""
+            };
+
+            Ok(ExecOutput {
+                exit_code: 0,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            })
+        });
+
+        let migrations = vec![
+            migration(2, "create table b ();"),
+            migration(3, "create table c ();"),
+        ];
+
+        let migrator = Migrator::new(&runtime, "app");
+        let states: Vec<(i64, MigrationState)> = migrator
+            .status(&migrations)
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.version, s.state))
+            .collect();
+        assert_eq!(
+            states,
+            vec![(2, MigrationState::Applied), (3, MigrationState::Pending)]
+        );
+        assert_eq!(
+            migrator.up(&migrations, Some(2)).unwrap(),
+            Vec::<i64>::new()
+        );
+    }
+}
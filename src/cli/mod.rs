@@ -1,12 +1,24 @@
 pub mod auth_client;
+pub mod cli_runtime;
 pub mod cloud_account;
+pub mod cloud_client;
 pub mod config;
+pub mod container_runtime;
 pub mod context;
 pub mod database;
 pub mod docker;
+pub mod docker_engine;
 pub mod extension;
 pub mod file_utils;
 pub mod instance;
+pub mod migrations;
+pub mod plan;
+pub mod replication;
+pub mod resources;
 pub mod schema;
 pub mod stacks;
 pub mod tembo_config;
+pub mod templates;
+pub mod topology;
+pub mod trunk;
+pub mod validation;
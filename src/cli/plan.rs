@@ -0,0 +1,333 @@
+//! A plan of the changes `tembo apply` would make, computed without touching Docker
+//! (rendered files are compared with the files on disk, settings with the running container)
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Add,
+    Change,
+    Remove,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Add => f.write_str("+"),
+            Action::Change => f.write_str("~"),
+            Action::Remove => f.write_str("-"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub action: Action,
+    pub resource: String,
+    pub details: Vec<String>,
+}
+
+// a postgres setting as reported by pg_settings, `setting` being in the setting's base `unit`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrentSetting {
+    pub display: String,
+    pub setting: String,
+    pub unit: String,
+    pub vartype: String,
+}
+
+impl CurrentSetting {
+    // a setting pg_settings doesn't know about, such as a placeholder for an extension's
+    pub fn custom(value: &str) -> CurrentSetting {
+        CurrentSetting {
+            display: value.to_string(),
+            setting: value.to_string(),
+            unit: String::new(),
+            vartype: String::from("string"),
+        }
+    }
+
+    // whether a tembo.toml value is the current one, numbers are compared in base units so
+    // `1GB` matches `1024MB` and booleans in any of the spellings postgres accepts
+    pub fn matches(&self, desired: &str) -> bool {
+        let desired = desired.trim();
+
+        match self.vartype.as_str() {
+            "bool" => match (parse_bool(desired), parse_bool(&self.setting)) {
+                (Some(desired), Some(current)) => desired == current,
+                _ => desired.eq_ignore_ascii_case(&self.setting),
+            },
+            "integer" | "real" => {
+                let current = self
+                    .setting
+                    .parse::<f64>()
+                    .ok()
+                    .zip(unit_factor(&self.unit))
+                    .map(|(value, factor)| value * factor);
+
+                match (in_base_unit(desired, &self.unit), current) {
+                    (Some(desired), Some(current)) => {
+                        (desired - current).abs() <= f64::EPSILON * current.abs().max(1.0)
+                    }
+                    _ => desired.eq_ignore_ascii_case(&self.setting),
+                }
+            }
+            _ => desired.eq_ignore_ascii_case(&self.display) || desired == self.setting,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub instance_name: String,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    pub fn new(instance_name: &str) -> Plan {
+        Plan {
+            instance_name: instance_name.to_string(),
+            changes: vec![],
+        }
+    }
+
+    pub fn has_changes(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    pub fn push(&mut self, action: Action, resource: String, details: Vec<String>) {
+        self.changes.push(Change {
+            action,
+            resource,
+            details,
+        });
+    }
+
+    pub fn count(&self, action: Action) -> usize {
+        self.changes.iter().filter(|c| c.action == action).count()
+    }
+
+    // compares a rendered file with the file on disk
+    pub fn diff_file(&mut self, path: &str, rendered: &str) {
+        match fs::read_to_string(path) {
+            Ok(existing) if existing == rendered => {}
+            Ok(existing) => {
+                self.push(
+                    Action::Change,
+                    format!("file {}", path),
+                    diff_lines(&existing, rendered),
+                );
+            }
+            Err(_) => self.push(Action::Add, format!("file {}", path), vec![]),
+        }
+    }
+
+    // compares the desired extensions with the ones installed in the database
+    pub fn diff_extensions(&mut self, desired: &[String], installed: &[String]) {
+        for name in desired {
+            if !installed.contains(name) {
+                self.push(Action::Add, format!("extension {}", name), vec![]);
+            }
+        }
+
+        for name in installed {
+            if !desired.contains(name) {
+                self.push(
+                    Action::Remove,
+                    format!("extension {}", name),
+                    vec![String::from(
+                        "dropped with CASCADE, requires --allow-destructive",
+                    )],
+                );
+            }
+        }
+    }
+
+    // compares a desired postgres setting with its current value
+    pub fn diff_setting(&mut self, name: &str, desired: &str, current: Option<&CurrentSetting>) {
+        match current {
+            Some(current) if current.matches(desired) => {}
+            Some(current) => self.push(
+                Action::Change,
+                format!("setting {}", name),
+                vec![format!("{} -> {}", current.display, desired)],
+            ),
+            None => self.push(
+                Action::Add,
+                format!("setting {}", name),
+                vec![desired.to_string()],
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Instance {}:", self.instance_name)?;
+
+        if !self.has_changes() {
+            return writeln!(f, "  No changes");
+        }
+
+        for change in &self.changes {
+            writeln!(f, "  {} {}", change.action, change.resource)?;
+
+            for detail in &change.details {
+                writeln!(f, "      {}", detail)?;
+            }
+        }
+
+        writeln!(
+            f,
+            "  Plan: {} to add, {} to change, {} to remove",
+            self.count(Action::Add),
+            self.count(Action::Change),
+            self.count(Action::Remove)
+        )
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Some(true),
+        "off" | "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// how many bytes or milliseconds a unit of pg_settings is, which may carry a multiple like `8kB`
+fn unit_factor(unit: &str) -> Option<f64> {
+    let digits = unit.chars().take_while(|c| c.is_ascii_digit()).count();
+    let (multiple, unit) = unit.split_at(digits);
+    let multiple = if multiple.is_empty() {
+        1.0
+    } else {
+        multiple.parse::<f64>().ok()?
+    };
+
+    let factor = match unit {
+        "" | "B" | "ms" => 1.0,
+        "kB" => 1024.0,
+        "MB" => 1024.0 * 1024.0,
+        "GB" => 1024.0 * 1024.0 * 1024.0,
+        "TB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        "us" => 0.001,
+        "s" => 1000.0,
+        "min" => 60_000.0,
+        "h" => 3_600_000.0,
+        "d" => 86_400_000.0,
+        _ => return None,
+    };
+
+    Some(multiple * factor)
+}
+
+// a value such as `64MB` or `60` in the base unit of a setting, bare numbers being in its unit
+fn in_base_unit(value: &str, unit: &str) -> Option<f64> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+
+    match suffix.trim() {
+        "" => Some(number * unit_factor(unit)?),
+        suffix => Some(number * unit_factor(suffix)?),
+    }
+}
+
+// returns the lines removed from and added to a file, prefixed with - and +
+fn diff_lines(existing: &str, rendered: &str) -> Vec<String> {
+    let existing_lines: HashSet<&str> = existing.lines().collect();
+    let rendered_lines: HashSet<&str> = rendered.lines().collect();
+
+    let removed = existing
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !rendered_lines.contains(l))
+        .map(|l| format!("- {}", l));
+    let added = rendered
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !existing_lines.contains(l))
+        .map(|l| format!("+ {}", l));
+
+    removed.chain(added).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_test() {
+        let mut plan = Plan::new("app");
+        assert!(!plan.has_changes());
+
+        plan.diff_extensions(
+            &[String::from("pgmq"), String::from("pg_stat_statements")],
+            &[String::from("pg_stat_statements"), String::from("hstore")],
+        );
+        let work_mem = CurrentSetting {
+            display: String::from("4MB"),
+            setting: String::from("4096"),
+            unit: String::from("kB"),
+            vartype: String::from("integer"),
+        };
+        plan.diff_setting("work_mem", "64MB", Some(&work_mem));
+        plan.diff_setting(
+            "statement_timeout",
+            "60",
+            Some(&CurrentSetting::custom("60")),
+        );
+
+        assert_eq!(plan.count(Action::Add), 1);
+        assert_eq!(plan.count(Action::Change), 1);
+        assert_eq!(plan.count(Action::Remove), 1);
+        assert_eq!(plan.changes[0].resource, "extension pgmq");
+        assert_eq!(plan.changes[2].details, vec!["4MB -> 64MB"]);
+    }
+
+    #[test]
+    fn current_setting_matches_test() {
+        let shared_buffers = CurrentSetting {
+            display: String::from("1GB"),
+            setting: String::from("131072"),
+            unit: String::from("8kB"),
+            vartype: String::from("integer"),
+        };
+        assert!(shared_buffers.matches("1GB"));
+        assert!(shared_buffers.matches("1024MB"));
+        assert!(shared_buffers.matches("131072"));
+        assert!(!shared_buffers.matches("2GB"));
+
+        let statement_timeout = CurrentSetting {
+            display: String::from("1min"),
+            setting: String::from("60000"),
+            unit: String::from("ms"),
+            vartype: String::from("integer"),
+        };
+        assert!(statement_timeout.matches("60s"));
+        assert!(statement_timeout.matches("60000"));
+
+        let fsync = CurrentSetting {
+            display: String::from("on"),
+            setting: String::from("on"),
+            unit: String::new(),
+            vartype: String::from("bool"),
+        };
+        assert!(fsync.matches("true"));
+        assert!(!fsync.matches("off"));
+    }
+
+    #[test]
+    fn diff_lines_test() {
+        let existing = "FROM base\nRUN trunk install pgmq\n";
+        let rendered = "FROM base\nRUN trunk install pg_later\n";
+
+        assert_eq!(
+            diff_lines(existing, rendered),
+            vec!["- RUN trunk install pgmq", "+ RUN trunk install pg_later"]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Conversion of an instance's cpu, memory and storage settings into local container limits
+//! and memory related Postgres settings, so local instances behave like cloud ones
+
+use crate::cli::tembo_config::InstanceSettings;
+use crate::Result;
+use anyhow::bail;
+
+const MEBIBYTE: u64 = 1024 * 1024;
+
+// Postgres settings derived from memory when not set in postgres_configurations,
+// as a percentage of the instance's memory
+const MEMORY_CONFIG_PERCENTAGES: &[(&str, u64)] = &[
+    ("shared_buffers", 25),
+    ("effective_cache_size", 75),
+    ("maintenance_work_mem", 5),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceLimits {
+    pub cpus: f64,
+    pub memory_bytes: u64,
+    pub storage_bytes: u64,
+}
+
+impl ResourceLimits {
+    pub fn from_settings(instance_setting: &InstanceSettings) -> Result<ResourceLimits> {
+        Ok(ResourceLimits {
+            cpus: parse_cpu(&instance_setting.cpu)?,
+            memory_bytes: parse_quantity(&instance_setting.memory)?,
+            storage_bytes: parse_quantity(&instance_setting.storage)?,
+        })
+    }
+
+    // memory settings sized relative to the instance's memory, skipping the ones already set
+    pub fn default_postgres_configs(
+        &self,
+        instance_setting: &InstanceSettings,
+    ) -> Vec<(String, String)> {
+        let memory_mb = self.memory_bytes / MEBIBYTE;
+
+        MEMORY_CONFIG_PERCENTAGES
+            .iter()
+            .filter(|(name, _)| !instance_setting.postgres_configurations.contains_key(*name))
+            .map(|(name, percentage)| {
+                (
+                    name.to_string(),
+                    format!("{}MB", memory_mb * percentage / 100),
+                )
+            })
+            .collect()
+    }
+}
+
+// parses a cpu setting such as "1", "0.5" or "500m" into a number of cores
+pub fn parse_cpu(cpu: &str) -> Result<f64> {
+    let cores = match cpu.strip_suffix('m') {
+        Some(millis) => millis.parse::<f64>().map(|m| m / 1000.0),
+        None => cpu.parse::<f64>(),
+    };
+
+    match cores {
+        Ok(c) if c > 0.0 => Ok(c),
+        _ => bail!("Invalid cpu `{}`", cpu),
+    }
+}
+
+// parses a quantity such as "512Mi" or "2Gi" into bytes
+pub fn parse_quantity(quantity: &str) -> Result<u64> {
+    let units: [(&str, u64); 4] = [
+        ("Ki", 1024),
+        ("Mi", MEBIBYTE),
+        ("Gi", 1024 * MEBIBYTE),
+        ("Ti", 1024 * 1024 * MEBIBYTE),
+    ];
+
+    for (unit, multiplier) in units {
+        if let Some(number) = quantity.strip_suffix(unit) {
+            if let Ok(n) = number.parse::<f64>() {
+                return Ok((n * multiplier as f64) as u64);
+            }
+        }
+    }
+
+    bail!(
+        "Invalid quantity `{}`, expected a value such as \"2Gi\"",
+        quantity
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use toml::Value;
+
+    #[test]
+    fn resource_limits_test() {
+        assert_eq!(parse_cpu("2").unwrap(), 2.0);
+        assert_eq!(parse_cpu("500m").unwrap(), 0.5);
+        assert!(parse_cpu("fast").is_err());
+        assert_eq!(parse_quantity("512Mi").unwrap(), 512 * MEBIBYTE);
+        assert_eq!(parse_quantity("1.5Gi").unwrap(), 1536 * MEBIBYTE);
+        assert!(parse_quantity("2GB").is_err());
+
+        let mut postgres_configurations = HashMap::new();
+        postgres_configurations.insert(
+            String::from("shared_buffers"),
+            Value::String(String::from("128MB")),
+        );
+        let instance_setting = InstanceSettings {
+            instance_name: String::from("app"),
+            port: 5432,
+            environment: String::from("dev"),
+            stack_type: String::from("Standard"),
+            pg_version: 15,
+            template_dir: None,
+            cpu: String::from("500m"),
+            memory: String::from("4Gi"),
+            storage: String::from("10Gi"),
+            replicas: 1,
+            postgres_configurations,
+            extensions: HashMap::new(),
+            databases: HashMap::new(),
+            roles: HashMap::new(),
+            default_privileges: vec![],
+            extension_libraries: HashMap::new(),
+        };
+
+        let limits = ResourceLimits::from_settings(&instance_setting).unwrap();
+        assert_eq!(limits.cpus, 0.5);
+        assert_eq!(limits.memory_bytes, 4096 * MEBIBYTE);
+        assert_eq!(limits.storage_bytes, 10240 * MEBIBYTE);
+        assert_eq!(
+            limits.default_postgres_configs(&instance_setting),
+            vec![
+                (String::from("effective_cache_size"), String::from("3072MB")),
+                (String::from("maintenance_work_mem"), String::from("204MB")),
+            ]
+        );
+    }
+}
@@ -1,14 +1,24 @@
 //! Stacks are defined templates provided by Tembo containing attributes and extensions
 //! (templates contain configuration information tailored to a specific use case)
+//!
+//! The official stacks are embedded in the binary, stack files in ~/.tembo/stacks and in the
+//! project's .tembo/stacks add to them, replacing stacks of the same name
 
+use crate::cli::context::tembo_home_dir;
 use crate::cli::extension::Extension;
+use crate::cli::tembo_config::DEFAULT_DATABASE;
 use crate::Result;
 use crate::{Deserialize, Serialize};
-use anyhow::bail;
+use anyhow::{bail, Context};
 use chrono::{DateTime, Utc};
 use clap::ArgMatches;
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+const EMBEDDED_STACKS: &str = include_str!("../../tembo/stacks.yaml");
+// stack files of the current project, relative to where the CLI is run
+pub const PROJECT_STACKS_DIR: &str = ".tembo/stacks";
 
 // object containing all of the defined stacks
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -20,10 +30,20 @@ pub struct Stacks {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Stack {
     pub name: String,
+    #[serde(default)]
     pub description: String,
+    #[serde(default)]
     pub version: String,
+    #[serde(default)]
     pub trunk_installs: Vec<TrunkInstall>,
+    #[serde(default)]
     pub extensions: Vec<Extension>,
+    #[serde(default)]
+    pub postgres_config: Vec<StackPostgresConfig>,
+    // resources of instances built from the stack, as in tembo.toml, e.g. "2Gi"
+    pub cpu: Option<String>,
+    pub memory: Option<String>,
+    pub storage: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -33,9 +53,107 @@ pub struct TrunkInstall {
     pub created_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StackPostgresConfig {
+    pub name: String,
+    pub value: String,
+}
+
+// a stack file holds either a list of stacks, like the embedded catalog, or a single stack
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StackFile {
+    Many(Stacks),
+    One(Stack),
+}
+
+impl Stacks {
+    // the stack with the given name, ignoring case
+    pub fn find(&self, name: &str) -> Option<&Stack> {
+        self.stacks
+            .iter()
+            .find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Stack {
+    // the stack as a tembo.toml instance table, the base an instance's own settings override
+    pub fn settings_table(&self) -> Table {
+        let mut table = Table::new();
+        table.insert(String::from("stack_type"), Value::String(self.name.clone()));
+
+        for (key, value) in [
+            ("cpu", &self.cpu),
+            ("memory", &self.memory),
+            ("storage", &self.storage),
+        ] {
+            if let Some(value) = value {
+                table.insert(key.to_string(), Value::String(value.clone()));
+            }
+        }
+
+        let postgres_configurations: Table = self
+            .postgres_config
+            .iter()
+            .map(|c| (c.name.clone(), Value::String(c.value.clone())))
+            .collect();
+        table.insert(
+            String::from("postgres_configurations"),
+            Value::Table(postgres_configurations),
+        );
+
+        let mut extensions = Table::new();
+
+        for extension in &self.extensions {
+            let name = extension.name.clone().unwrap_or_default();
+            let mut settings = Table::new();
+            settings.insert(String::from("enabled"), Value::Boolean(true));
+
+            let install = self
+                .trunk_installs
+                .iter()
+                .find(|i| i.name.as_deref() == Some(name.as_str()));
+            if let Some(install) = install {
+                settings.insert(String::from("trunk_project"), Value::String(name.clone()));
+                if let Some(version) = &install.version {
+                    settings.insert(
+                        String::from("trunk_project_version"),
+                        Value::String(version.clone()),
+                    );
+                }
+            }
+
+            // extensions only in the default database need no locations
+            if extension
+                .locations
+                .iter()
+                .any(|l| l.database != DEFAULT_DATABASE)
+            {
+                let locations = extension
+                    .locations
+                    .iter()
+                    .map(|l| {
+                        let mut location = Table::new();
+                        location
+                            .insert(String::from("database"), Value::String(l.database.clone()));
+                        Value::Table(location)
+                    })
+                    .collect();
+                settings.insert(String::from("locations"), Value::Array(locations));
+            }
+
+            extensions.insert(name, Value::Table(settings));
+        }
+
+        table.insert(String::from("extensions"), Value::Table(extensions));
+
+        table
+    }
+}
+
 // returns a result containing the stack name
 pub fn define_stack(args: &ArgMatches) -> Result<String> {
-    let stacks: Stacks = define_stacks();
+    let stacks: Stacks = define_stacks()?;
     let names: Vec<String> = stacks
         .stacks
         .clone()
@@ -59,16 +177,69 @@ pub fn define_stack(args: &ArgMatches) -> Result<String> {
     }
 }
 
-// returns a Stacks object containing attributes loaded from the stacks.yml file
-pub fn define_stacks() -> Stacks {
-    let file = "./tembo/stacks.yaml"; // TODO: move to a constant
-    let mut file = File::open(file).expect("Unable to open stack config file");
-    let mut contents = String::new();
+// returns the embedded stacks along with those in the user's and the project's stack directories
+pub fn define_stacks() -> Result<Stacks> {
+    load_stacks(&[
+        Path::new(&tembo_home_dir()).join("stacks"),
+        PathBuf::from(PROJECT_STACKS_DIR),
+    ])
+}
+
+// the embedded stacks, then the stacks of each directory in turn, a stack replacing any earlier
+// one of the same name
+fn load_stacks(dirs: &[PathBuf]) -> Result<Stacks> {
+    let mut stacks: Stacks = serde_yaml::from_str(EMBEDDED_STACKS)
+        .with_context(|| "Unable to parse the embedded stacks")?;
 
-    file.read_to_string(&mut contents)
-        .expect("Unable to read stack config file");
+    for dir in dirs {
+        for stack in read_stack_dir(dir)? {
+            match stacks
+                .stacks
+                .iter_mut()
+                .find(|s| s.name.eq_ignore_ascii_case(&stack.name))
+            {
+                Some(existing) => *existing = stack,
+                None => stacks.stacks.push(stack),
+            }
+        }
+    }
 
-    serde_yaml::from_str(&contents).unwrap()
+    Ok(stacks)
+}
+
+// the stacks in the directory's yaml files, in file name order, none when it doesn't exist
+fn read_stack_dir(dir: &Path) -> Result<Vec<Stack>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Couldn't read stack directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("yaml") | Some("yml")
+            )
+        })
+        .collect();
+    paths.sort();
+
+    let mut stacks = vec![];
+
+    for path in paths {
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Couldn't read stack file {}", path.display()))?;
+
+        match serde_yaml::from_str(&contents)
+            .with_context(|| format!("Unable to parse stack file {}", path.display()))?
+        {
+            StackFile::Many(many) => stacks.extend(many.stacks),
+            StackFile::One(one) => stacks.push(one),
+        }
+    }
+
+    Ok(stacks)
 }
 
 #[cfg(test)]
@@ -104,4 +275,35 @@ mod tests {
         let result = define_stack(&matches).err().unwrap().to_string();
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn load_stacks_test() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = tempdir.path().to_path_buf();
+        fs::write(
+            dir.join("standard.yaml"),
+            "name: standard\ndescription: Tuned for our team\nversion: 0.2.0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("geo.yml"),
+            "stacks:\n  - name: Geo\n    trunk_installs:\n      - name: postgis\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not a stack").unwrap();
+
+        let stacks = load_stacks(&[dir.clone(), dir.join("missing")]).unwrap();
+
+        // the user stack replaces the embedded one of the same name
+        let standard = stacks.find("Standard").unwrap();
+        assert_eq!(standard.description, "Tuned for our team");
+        assert!(standard.trunk_installs.is_empty());
+
+        assert!(stacks.find("data-warehouse").is_some());
+        assert_eq!(
+            stacks.find("geo").unwrap().trunk_installs[0].name,
+            Some(String::from("postgis"))
+        );
+        assert_eq!(stacks.stacks.len(), 3);
+    }
 }
@@ -1,22 +1,91 @@
+use crate::cli::stacks::{define_stacks, Stacks};
+use crate::cli::trunk::{select_version, TrunkRegistry};
+use crate::Result;
+use anyhow::{bail, Context};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use toml::Value;
+use std::collections::{BTreeMap, HashMap};
+use toml::{Table, Value};
+use toml_edit::{value, Array, Document, InlineTable, Item, TableLike};
 
+pub const VERSION_KEY: &str = "version";
+pub const DEFAULTS_KEY: &str = "defaults";
+// the major Postgres versions instances can run
+pub const PG_VERSIONS: &[u8] = &[14, 15, 16];
+pub const DEFAULT_PG_VERSION: u8 = 15;
+// local instances run this image, tagged with the major Postgres version and shipping Trunk
+pub const BASE_IMAGE_REPOSITORY: &str = "quay.io/tembo/tembo-local";
+// names a stack whose settings an instance starts from
+pub const STACK_KEY: &str = "stack";
+// the database extensions are created in when they have no locations
+pub const DEFAULT_DATABASE: &str = "postgres";
+pub const PRELOAD_SETTING: &str = "shared_preload_libraries";
+
+// extensions whose library must be loaded at server start, with the library's name, in the order
+// they are added to shared_preload_libraries. The Trunk registry has the final say, this list
+// covers extensions it wasn't asked about or couldn't be reached for
+const PRELOAD_LIBRARIES: &[(&str, &str)] = &[
+    ("pg_stat_statements", "pg_stat_statements"),
+    ("pg_stat_kcache", "pg_stat_kcache"),
+    ("pg_cron", "pg_cron"),
+    ("pg_partman", "pg_partman_bgw"),
+    ("pg_later", "pg_later"),
+    ("pgaudit", "pgaudit"),
+    ("pg_net", "pg_net"),
+    ("pgsodium", "pgsodium"),
+    ("pg_squeeze", "pg_squeeze"),
+    ("pg_wait_sampling", "pg_wait_sampling"),
+    ("pglogical", "pglogical"),
+    ("timescaledb", "timescaledb"),
+];
+
+// the contents of tembo.toml: an optional version, a `[defaults]` table that every instance
+// inherits from, and one table per instance
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct TemboConfig {
-    pub version: String,
-    pub defaults: InstanceSettings,
+    pub version: Option<String>,
+    pub defaults: Table,
+    pub instances: BTreeMap<String, Table>,
 }
 
 // Config struct holds to data from the `[config]` section.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct InstanceSettings {
+    // populated from the table name in tembo.toml, e.g. `[my-app-db]`
+    #[serde(skip)]
+    pub instance_name: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_environment")]
+    pub environment: String,
+    #[serde(default = "default_stack_type")]
+    pub stack_type: String,
+    // the major Postgres version, one of PG_VERSIONS
+    #[serde(default = "default_pg_version")]
+    pub pg_version: u8,
+    // directory with templates overriding the ones bundled in the binary
+    pub template_dir: Option<String>,
+    #[serde(default = "default_cpu")]
     pub cpu: String,
+    #[serde(default = "default_memory")]
     pub memory: String,
+    #[serde(default = "default_storage")]
     pub storage: String,
+    #[serde(default = "default_replicas")]
     pub replicas: u32,
+    #[serde(default)]
     pub postgres_configurations: HashMap<String, Value>,
+    #[serde(default)]
     pub extensions: HashMap<String, Extension>,
+    #[serde(default)]
+    pub databases: HashMap<String, DatabaseSettings>,
+    #[serde(default)]
+    pub roles: HashMap<String, RoleSettings>,
+    #[serde(default)]
+    pub default_privileges: Vec<DefaultPrivilege>,
+    // the libraries the Trunk registry says each enabled extension needs preloaded, see
+    // resolve_preload_libraries
+    #[serde(skip)]
+    pub extension_libraries: HashMap<String, Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -24,4 +93,754 @@ pub struct Extension {
     pub enabled: bool,
     pub trunk_project: Option<String>,
     pub trunk_project_version: Option<String>,
+    // the version CREATE EXTENSION creates, changing it updates existing installs with
+    // ALTER EXTENSION ... UPDATE TO
+    pub extension_version: Option<String>,
+    // where the extension is created, the default database when empty
+    #[serde(default)]
+    pub locations: Vec<ExtensionLocation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ExtensionLocation {
+    #[serde(default = "default_database")]
+    pub database: String,
+    // created if missing, the database's default schema when not set
+    pub schema: Option<String>,
+    pub version: Option<String>,
+}
+
+// a database created by apply, along with its schemas
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct DatabaseSettings {
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub schemas: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct RoleSettings {
+    #[serde(default)]
+    pub login: bool,
+    // the environment variable holding the password, so it never lives in tembo.toml
+    pub password_env: Option<String>,
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+}
+
+// privileges on a database, a schema, or all tables, sequences or functions in a schema
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Grant {
+    pub database: String,
+    pub schema: Option<String>,
+    // database, schema, tables, sequences or functions, tables when a schema is given
+    pub on: Option<String>,
+    pub privileges: Vec<String>,
+}
+
+// privileges granted on objects created later in a schema
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DefaultPrivilege {
+    pub database: String,
+    pub schema: String,
+    // the role the privileges are granted to
+    pub role: String,
+    // the role whose new objects are covered, the one running apply when not set
+    pub for_role: Option<String>,
+    // tables, sequences or functions
+    #[serde(default = "default_privilege_objects")]
+    pub on: String,
+    pub privileges: Vec<String>,
+}
+
+impl TemboConfig {
+    pub fn parse(contents: &str) -> Result<TemboConfig> {
+        let mut document: Table = toml::from_str(contents)?;
+
+        let version = match document.remove(VERSION_KEY) {
+            Some(Value::String(v)) => Some(v),
+            Some(_) => bail!("`{}` must be a string", VERSION_KEY),
+            None => None,
+        };
+
+        let defaults = match document.remove(DEFAULTS_KEY) {
+            Some(Value::Table(t)) => t,
+            Some(_) => bail!("`{}` must be a table", DEFAULTS_KEY),
+            None => Table::new(),
+        };
+
+        let mut instances = BTreeMap::new();
+
+        for (name, value) in document {
+            match value {
+                Value::Table(t) => instances.insert(name, t),
+                _ => bail!("Instance `{}` must be a table", name),
+            };
+        }
+
+        Ok(TemboConfig {
+            version,
+            defaults,
+            instances,
+        })
+    }
+
+    // returns the instance's table merged over the defaults, instance values taking precedence
+    pub fn resolved_table(&self, name: &str) -> Option<Table> {
+        let instance = self.instances.get(name)?;
+
+        let mut resolved = self.defaults.clone();
+        merge_tables(&mut resolved, instance);
+
+        Some(resolved)
+    }
+
+    // returns the resolved table of the instance on top of its stack when it names one, the
+    // stacks being loaded on first use
+    pub fn merged_table(&self, name: &str, stacks: &mut Option<Stacks>) -> Result<Table> {
+        let mut resolved = self
+            .resolved_table(name)
+            .with_context(|| format!("Instance {} not found in tembo.toml", name))?;
+
+        if let Some(stack) = resolved.remove(STACK_KEY) {
+            let stack = stack
+                .as_str()
+                .with_context(|| format!("stack of instance {} must be a string", name))?;
+
+            if stacks.is_none() {
+                *stacks = Some(define_stacks()?);
+            }
+            let mut base = stacks
+                .as_ref()
+                .unwrap()
+                .find(stack)
+                .with_context(|| {
+                    format!(
+                        "Stack {} of instance {} is not defined, see `tembo stack list`",
+                        stack, name
+                    )
+                })?
+                .settings_table();
+
+            merge_tables(&mut base, &resolved);
+            resolved = base;
+        }
+
+        Ok(resolved)
+    }
+
+    // returns the effective settings of every instance, on top of its stack when it names one
+    pub fn instance_settings(&self) -> Result<HashMap<String, InstanceSettings>> {
+        let mut instance_settings = HashMap::new();
+        let mut stacks: Option<Stacks> = None;
+
+        for name in self.instances.keys() {
+            let resolved = self.merged_table(name, &mut stacks)?;
+
+            let mut instance_setting: InstanceSettings = Value::Table(resolved)
+                .try_into()
+                .with_context(|| format!("Unable to load settings for instance {}", name))?;
+            instance_setting.instance_name = name.clone();
+
+            instance_settings.insert(name.clone(), instance_setting);
+        }
+
+        Ok(instance_settings)
+    }
+}
+
+// adds the Trunk extension to an instance of tembo.toml, enabled in the databases when any are
+// given, keeping the rest of the file as written
+pub fn add_extension(
+    contents: &str,
+    instance_name: &str,
+    name: &str,
+    version: Option<&str>,
+    databases: &[String],
+) -> Result<String> {
+    let mut document: Document = contents.parse()?;
+
+    let instance = document
+        .get_mut(instance_name)
+        .and_then(Item::as_table_like_mut)
+        .with_context(|| format!("Instance {} is not in tembo.toml", instance_name))?;
+
+    if instance.get("extensions").is_none() {
+        let mut extensions = toml_edit::Table::new();
+        extensions.set_implicit(true);
+        instance.insert("extensions", Item::Table(extensions));
+    }
+    let extensions = instance
+        .get_mut("extensions")
+        .and_then(Item::as_table_like_mut)
+        .with_context(|| format!("extensions of instance {} must be a table", instance_name))?;
+
+    if extensions.get(name).is_none() {
+        extensions.insert(name, Item::Table(toml_edit::Table::new()));
+    }
+    let extension = extensions
+        .get_mut(name)
+        .and_then(Item::as_table_like_mut)
+        .with_context(|| format!("extension {} must be a table", name))?;
+
+    extension.insert("enabled", value(true));
+    extension.insert("trunk_project", value(name));
+    if let Some(version) = version {
+        extension.insert("trunk_project_version", value(version));
+    }
+
+    if !databases.is_empty() {
+        let mut locations = Array::new();
+        for database in databases {
+            let mut location = InlineTable::new();
+            location.insert("database", database.as_str().into());
+            locations.push(location);
+        }
+        extension.insert("locations", value(locations));
+    }
+
+    Ok(document.to_string())
+}
+
+// sets pg_version of the instance in tembo.toml, keeping the file's formatting and comments
+pub fn set_pg_version(contents: &str, instance_name: &str, pg_version: u8) -> Result<String> {
+    let mut document: Document = contents.parse()?;
+
+    let instance = document
+        .get_mut(instance_name)
+        .and_then(Item::as_table_like_mut)
+        .with_context(|| format!("Instance {} is not in tembo.toml", instance_name))?;
+
+    set_value(instance, "pg_version", i64::from(pg_version).into());
+
+    Ok(document.to_string())
+}
+
+// sets the Trunk and extension versions of an extension of the instance in tembo.toml, along
+// with the version of the locations that pin one
+pub fn set_extension_versions(
+    contents: &str,
+    instance_name: &str,
+    name: &str,
+    trunk_project_version: &str,
+    extension_version: &str,
+) -> Result<String> {
+    let mut document: Document = contents.parse()?;
+
+    let extension = document
+        .get_mut(instance_name)
+        .and_then(Item::as_table_like_mut)
+        .and_then(|instance| instance.get_mut("extensions"))
+        .and_then(Item::as_table_like_mut)
+        .and_then(|extensions| extensions.get_mut(name))
+        .and_then(Item::as_table_like_mut)
+        .with_context(|| {
+            format!(
+                "Extension {} of instance {} is not in tembo.toml",
+                name, instance_name
+            )
+        })?;
+
+    set_value(
+        extension,
+        "trunk_project_version",
+        trunk_project_version.into(),
+    );
+    set_value(extension, "extension_version", extension_version.into());
+
+    match extension.get_mut("locations") {
+        Some(Item::Value(toml_edit::Value::Array(locations))) => {
+            for location in locations.iter_mut().filter_map(|l| l.as_inline_table_mut()) {
+                if location.contains_key("version") {
+                    set_value(location, "version", extension_version.into());
+                }
+            }
+        }
+        Some(Item::ArrayOfTables(locations)) => {
+            for location in locations.iter_mut() {
+                if location.contains_key("version") {
+                    set_value(location, "version", extension_version.into());
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(document.to_string())
+}
+
+// sets a value of a table, keeping the comment and spacing around the value it replaces
+fn set_value(table: &mut dyn TableLike, key: &str, new_value: toml_edit::Value) {
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = new_value;
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table.insert(key, Item::Value(new_value));
+        }
+    }
+}
+
+// recursively merges `overrides` into `base`, so maps such as extensions and
+// postgres_configurations combine rather than replace each other
+fn merge_tables(base: &mut Table, overrides: &Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(key), value) {
+            (Some(Value::Table(base_table)), Value::Table(override_table)) => {
+                merge_tables(base_table, override_table)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+impl InstanceSettings {
+    // the extensions that should exist in the default database, sorted by name
+    pub fn enabled_extensions(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .extensions
+            .iter()
+            .filter(|(_, extension)| {
+                extension.enabled
+                    && (extension.locations.is_empty()
+                        || extension
+                            .locations
+                            .iter()
+                            .any(|l| l.database == DEFAULT_DATABASE))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+
+        names
+    }
+
+    // the explicit locations of enabled extensions, sorted by extension name, versions default to
+    // the extension's extension_version
+    pub fn extension_locations(&self) -> Vec<(String, ExtensionLocation)> {
+        let mut locations: Vec<(String, ExtensionLocation)> = self
+            .extensions
+            .iter()
+            .filter(|(_, extension)| extension.enabled)
+            .flat_map(|(name, extension)| {
+                extension.locations.iter().map(move |location| {
+                    let mut location = location.clone();
+                    location.version = location
+                        .version
+                        .or_else(|| extension.extension_version.clone());
+
+                    (name.clone(), location)
+                })
+            })
+            .collect();
+        locations.sort_by(|a, b| a.0.cmp(&b.0));
+
+        locations
+    }
+
+    // the libraries set in postgres_configurations, in their order, followed by those the enabled
+    // extensions need loaded
+    pub fn preload_libraries(&self) -> Result<Vec<String>> {
+        let mut libraries: Vec<String> = self
+            .postgres_config_pairs()?
+            .into_iter()
+            .filter(|(name, _)| name == PRELOAD_SETTING)
+            .flat_map(|(_, value)| split_libraries(&value))
+            .collect();
+
+        // extensions of the built-in list keep its order, the others follow by name
+        let mut enabled: Vec<&String> = self
+            .extensions
+            .iter()
+            .filter(|(_, e)| e.enabled)
+            .map(|(name, _)| name)
+            .collect();
+        enabled.sort_by_key(|name| {
+            (
+                PRELOAD_LIBRARIES
+                    .iter()
+                    .position(|(e, _)| e == name)
+                    .unwrap_or(usize::MAX),
+                name.to_string(),
+            )
+        });
+
+        for name in enabled {
+            let needed: Vec<String> = match self.extension_libraries.get(name) {
+                Some(needed) => needed.clone(),
+                None => PRELOAD_LIBRARIES
+                    .iter()
+                    .filter(|(e, _)| e == name)
+                    .map(|(_, library)| library.to_string())
+                    .collect(),
+            };
+
+            for library in needed {
+                if !libraries.contains(&library) {
+                    libraries.push(library);
+                }
+            }
+        }
+
+        Ok(libraries)
+    }
+
+    // looks up the libraries each enabled extension's Trunk project needs preloaded, failing only
+    // when the registry can't be reached. Extensions the registry doesn't know are left to the
+    // built-in list
+    pub fn resolve_preload_libraries(&mut self, registry: &TrunkRegistry) -> Result<()> {
+        for (name, extension) in self.extensions.iter().filter(|(_, e)| e.enabled) {
+            let project = extension.trunk_project.as_deref().unwrap_or(name);
+            let versions = registry.versions(project)?;
+
+            if let Ok(project) = select_version(
+                project,
+                &versions,
+                extension.trunk_project_version.as_deref(),
+            ) {
+                self.extension_libraries
+                    .insert(name.clone(), project.preload_libraries());
+            }
+        }
+
+        Ok(())
+    }
+
+    // flattens postgres_configurations into name/value pairs sorted by name, nested tables
+    // become `key.name`
+    pub fn postgres_config_pairs(&self) -> Result<Vec<(String, String)>> {
+        let mut pairs = vec![];
+
+        for (key, value) in self.postgres_configurations.iter() {
+            match value {
+                Value::String(v) => pairs.push((key.clone(), v.clone())),
+                Value::Table(table) => {
+                    for (t, v) in table.iter() {
+                        let v = v.as_str().with_context(|| {
+                            format!(
+                                "postgres configuration {}.{} of instance {} must be a string",
+                                key, t, self.instance_name
+                            )
+                        })?;
+                        pairs.push((format!("{}.{}", key, t), v.to_string()));
+                    }
+                }
+                _ => bail!(
+                    "postgres configuration {} of instance {} must be a string",
+                    key,
+                    self.instance_name
+                ),
+            }
+        }
+
+        pairs.sort();
+
+        Ok(pairs)
+    }
+}
+
+// splits a shared_preload_libraries value, as set in tembo.toml or shown by postgres
+pub fn split_libraries(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|l| l.trim().trim_matches('"').to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+fn default_privilege_objects() -> String {
+    String::from("tables")
+}
+
+fn default_database() -> String {
+    DEFAULT_DATABASE.to_string()
+}
+
+fn default_port() -> u16 {
+    5432
+}
+
+// the image local instances of a major Postgres version are built from
+pub fn base_image(pg_version: u8) -> String {
+    format!("{}:{}", BASE_IMAGE_REPOSITORY, pg_version)
+}
+
+fn default_pg_version() -> u8 {
+    DEFAULT_PG_VERSION
+}
+
+fn default_cpu() -> String {
+    String::from("1")
+}
+
+fn default_memory() -> String {
+    String::from("2Gi")
+}
+
+fn default_storage() -> String {
+    String::from("10Gi")
+}
+
+fn default_replicas() -> u32 {
+    1
+}
+
+fn default_environment() -> String {
+    String::from("dev")
+}
+
+fn default_stack_type() -> String {
+    String::from("Standard")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolved_settings_test() {
+        let contents = r#"
+version = "1.0"
+
+[defaults]
+cpu = "1"
+memory = "2Gi"
+
+[defaults.postgres_configurations]
+statement_timeout = "60"
+
+[defaults.extensions.pg_stat_statements]
+enabled = true
+
+[app]
+memory = "4Gi"
+
+[app.postgres_configurations]
+work_mem = "64MB"
+
+[app.extensions.pgmq]
+enabled = true
+trunk_project = "pgmq"
+
+[analytics]
+port = 5433
+
+[analytics.extensions.pg_stat_statements]
+enabled = false
+"#;
+
+        let config = TemboConfig::parse(contents).unwrap();
+        assert_eq!(config.version, Some(String::from("1.0")));
+        assert_eq!(config.instances.len(), 2);
+
+        let settings = config.instance_settings().unwrap();
+
+        let app = &settings["app"];
+        assert_eq!(app.instance_name, "app");
+        assert_eq!(app.cpu, "1");
+        assert_eq!(app.memory, "4Gi");
+        assert_eq!(app.postgres_configurations.len(), 2);
+        assert_eq!(app.extensions.len(), 2);
+
+        let analytics = &settings["analytics"];
+        assert_eq!(analytics.memory, "2Gi");
+        assert_eq!(analytics.port, 5433);
+        assert!(!analytics.extensions["pg_stat_statements"].enabled);
+    }
+
+    #[test]
+    fn stack_settings_test() {
+        let contents = r#"
+[warehouse]
+stack = "data-warehouse"
+memory = "16Gi"
+
+[warehouse.postgres_configurations]
+work_mem = "128MB"
+
+[warehouse.extensions.pgmq]
+enabled = true
+"#;
+
+        let settings = TemboConfig::parse(contents)
+            .unwrap()
+            .instance_settings()
+            .unwrap();
+
+        let warehouse = &settings["warehouse"];
+        assert_eq!(warehouse.stack_type, "Data-Warehouse");
+        assert_eq!(warehouse.cpu, "2");
+        assert_eq!(warehouse.memory, "16Gi");
+        assert_eq!(warehouse.storage, "50Gi");
+        assert_eq!(
+            warehouse.postgres_configurations["work_mem"],
+            Value::String(String::from("128MB"))
+        );
+        assert_eq!(
+            warehouse.postgres_configurations["max_parallel_workers"],
+            Value::String(String::from("8"))
+        );
+        assert_eq!(
+            warehouse.extensions["pg_later"].trunk_project_version,
+            Some(String::from("0.0.8"))
+        );
+        assert!(warehouse.extensions["pgmq"].enabled);
+
+        let unknown = TemboConfig::parse("[app]\nstack = \"nope\"\n").unwrap();
+        assert!(unknown.instance_settings().is_err());
+    }
+
+    #[test]
+    fn postgres_config_pairs_test() {
+        let contents = r#"
+[app.postgres_configurations]
+work_mem = "64MB"
+pg_partman_bgw = { dbname = "postgres" }
+"#;
+
+        let settings = TemboConfig::parse(contents)
+            .unwrap()
+            .instance_settings()
+            .unwrap();
+        assert_eq!(
+            settings["app"].postgres_config_pairs().unwrap(),
+            vec![
+                (
+                    String::from("pg_partman_bgw.dbname"),
+                    String::from("postgres")
+                ),
+                (String::from("work_mem"), String::from("64MB")),
+            ]
+        );
+
+        for invalid in ["work_mem = 64", "pg_partman_bgw = { interval = 3600 }"] {
+            let contents = format!("[app.postgres_configurations]\n{}\n", invalid);
+            let settings = TemboConfig::parse(&contents)
+                .unwrap()
+                .instance_settings()
+                .unwrap();
+
+            assert!(settings["app"].postgres_config_pairs().is_err());
+        }
+    }
+
+    #[test]
+    fn preload_libraries_test() {
+        let contents = r#"
+[app.postgres_configurations]
+shared_preload_libraries = "auto_explain, pg_stat_statements"
+
+[app.extensions.pg_stat_statements]
+enabled = true
+
+[app.extensions.pg_cron]
+enabled = true
+
+[app.extensions.pg_partman]
+enabled = false
+"#;
+
+        let settings = TemboConfig::parse(contents)
+            .unwrap()
+            .instance_settings()
+            .unwrap();
+
+        // user entries keep their order, only missing libraries are added
+        assert_eq!(
+            settings["app"].preload_libraries().unwrap(),
+            vec![
+                String::from("auto_explain"),
+                String::from("pg_stat_statements"),
+                String::from("pg_cron"),
+            ]
+        );
+
+        // what the registry says replaces the built-in list
+        let mut app = settings["app"].clone();
+        app.extension_libraries
+            .insert(String::from("pg_cron"), vec![]);
+        app.extension_libraries.insert(
+            String::from("pg_stat_statements"),
+            vec![
+                String::from("pg_stat_statements"),
+                String::from("pg_qualstats"),
+            ],
+        );
+        assert_eq!(
+            app.preload_libraries().unwrap(),
+            vec![
+                String::from("auto_explain"),
+                String::from("pg_stat_statements"),
+                String::from("pg_qualstats"),
+            ]
+        );
+    }
+
+    #[test]
+    fn add_extension_test() {
+        let contents = "# local development\n[app]\nport = 5432 # default\n";
+
+        let updated = add_extension(
+            contents,
+            "app",
+            "pgmq",
+            Some("1.1.1"),
+            &[String::from("postgres"), String::from("queues")],
+        )
+        .unwrap();
+        assert!(updated.starts_with(contents));
+
+        let settings = TemboConfig::parse(&updated)
+            .unwrap()
+            .instance_settings()
+            .unwrap();
+        let pgmq = &settings["app"].extensions["pgmq"];
+        assert!(pgmq.enabled);
+        assert_eq!(pgmq.trunk_project_version, Some(String::from("1.1.1")));
+        assert_eq!(pgmq.locations.len(), 2);
+        assert_eq!(pgmq.locations[1].database, "queues");
+
+        assert!(add_extension(contents, "analytics", "pgmq", None, &[]).is_err());
+    }
+
+    #[test]
+    fn set_pg_version_test() {
+        let contents = "[app]\npg_version = 15 # upgraded with tembo upgrade-pg\nport = 5432\n";
+
+        assert_eq!(
+            set_pg_version(contents, "app", 16).unwrap(),
+            "[app]\npg_version = 16 # upgraded with tembo upgrade-pg\nport = 5432\n"
+        );
+        assert_eq!(
+            set_pg_version("[app]\nport = 5432\n", "app", 16).unwrap(),
+            "[app]\nport = 5432\npg_version = 16\n"
+        );
+        assert!(set_pg_version(contents, "analytics", 16).is_err());
+    }
+
+    #[test]
+    fn set_extension_versions_test() {
+        let contents = r#"[app.extensions.pg_cron]
+enabled = true
+trunk_project = "pg_cron"
+trunk_project_version = "1.5.2" # pinned
+locations = [{ database = "postgres", version = "1.5" }, { database = "app" }]
+"#;
+
+        assert_eq!(
+            set_extension_versions(contents, "app", "pg_cron", "1.6.2", "1.6").unwrap(),
+            r#"[app.extensions.pg_cron]
+enabled = true
+trunk_project = "pg_cron"
+trunk_project_version = "1.6.2" # pinned
+locations = [{ database = "postgres", version = "1.6" }, { database = "app" }]
+extension_version = "1.6"
+"#
+        );
+        assert!(set_extension_versions(contents, "app", "pgmq", "1.1.1", "1.1.1").is_err());
+    }
 }
@@ -0,0 +1,87 @@
+//! Templates used by `tembo apply` to render each instance's Dockerfile and migrations
+//! (bundled into the binary, optionally overridden by files in a template directory)
+
+use crate::Result;
+use anyhow::{bail, Context};
+use std::fs;
+use std::path::Path;
+
+pub const DOCKERFILE_TEMPLATE_NAME: &str = "Dockerfile.template";
+pub const MIGRATIONS_TEMPLATE_NAME: &str = "migrations.sql.template";
+
+const EMBEDDED_DOCKERFILE_TEMPLATE: &str = include_str!("../../tembo/Dockerfile.template");
+const EMBEDDED_MIGRATIONS_TEMPLATE: &str = include_str!("../../tembo/migrations.sql.template");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Templates {
+    pub dockerfile: String,
+    pub migrations: String,
+    // describes where the templates came from, recorded in the rendered files
+    pub version: String,
+}
+
+impl Templates {
+    // returns the embedded templates, with any file present in template_dir taking precedence
+    pub fn load(template_dir: Option<&str>) -> Result<Templates> {
+        let embedded = Templates {
+            dockerfile: EMBEDDED_DOCKERFILE_TEMPLATE.to_string(),
+            migrations: EMBEDDED_MIGRATIONS_TEMPLATE.to_string(),
+            version: format!("tembo {} (embedded)", crate::VERSION),
+        };
+
+        let dir = match template_dir {
+            Some(dir) => dir,
+            None => return Ok(embedded),
+        };
+
+        if !Path::new(dir).is_dir() {
+            bail!("Template directory {} does not exist", dir);
+        }
+
+        Ok(Templates {
+            dockerfile: read_override(dir, DOCKERFILE_TEMPLATE_NAME)?
+                .unwrap_or(embedded.dockerfile),
+            migrations: read_override(dir, MIGRATIONS_TEMPLATE_NAME)?
+                .unwrap_or(embedded.migrations),
+            version: format!("{} (custom, tembo {})", dir, crate::VERSION),
+        })
+    }
+}
+
+fn read_override(dir: &str, name: &str) -> Result<Option<String>> {
+    let path = Path::new(dir).join(name);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Couldn't read template {}", path.display()))?;
+
+    Ok(Some(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_test() {
+        // embedded templates
+        let templates = Templates::load(None).unwrap();
+        assert!(templates.dockerfile.contains("trunk install"));
+        assert!(templates.version.contains("embedded"));
+
+        // a template directory overriding only the Dockerfile
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(DOCKERFILE_TEMPLATE_NAME), "FROM custom").unwrap();
+
+        let templates = Templates::load(Some(&dir.path().to_string_lossy())).unwrap();
+        assert_eq!(templates.dockerfile, "FROM custom");
+        assert_eq!(templates.migrations, EMBEDDED_MIGRATIONS_TEMPLATE);
+        assert!(templates.version.contains("custom"));
+
+        // a missing template directory
+        assert!(Templates::load(Some("/does/not/exist")).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+//! Databases, schemas, roles and privileges declared in tembo.toml, reconciled by apply on local
+//! instances. Objects are created and grants applied on every apply, nothing is revoked or dropped
+//! when it's removed from tembo.toml
+
+use crate::cli::container_runtime::ContainerRuntime;
+use crate::cli::docker::{quote_identifier, quote_literal, Docker};
+use crate::cli::plan::{Action, Plan};
+use crate::cli::tembo_config::{DefaultPrivilege, Grant, InstanceSettings};
+use crate::Result;
+use anyhow::{bail, Context};
+use simplelog::*;
+use std::env;
+
+pub const PRIVILEGES: &[&str] = &[
+    "ALL",
+    "ALL PRIVILEGES",
+    "SELECT",
+    "INSERT",
+    "UPDATE",
+    "DELETE",
+    "TRUNCATE",
+    "REFERENCES",
+    "TRIGGER",
+    "CREATE",
+    "CONNECT",
+    "TEMPORARY",
+    "TEMP",
+    "EXECUTE",
+    "USAGE",
+];
+
+// what a grant applies to, tables when it names a schema and database otherwise
+pub const GRANT_TARGETS: &[&str] = &["database", "schema", "tables", "sequences", "functions"];
+
+// the objects default privileges cover
+pub const DEFAULT_PRIVILEGE_TARGETS: &[&str] = &["tables", "sequences", "functions"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Object {
+    Role(String),
+    Database(String),
+    Schema(String, String),
+}
+
+impl Object {
+    fn resource(&self) -> String {
+        match self {
+            Object::Role(name) => format!("role {}", name),
+            Object::Database(name) => format!("database {}", name),
+            Object::Schema(database, name) => format!("schema {}.{}", database, name),
+        }
+    }
+}
+
+// the roles, databases and schemas of an instance, in the order they are created
+fn desired_objects(instance_setting: &InstanceSettings) -> Vec<Object> {
+    let mut roles: Vec<&String> = instance_setting.roles.keys().collect();
+    roles.sort();
+    let mut databases: Vec<&String> = instance_setting.databases.keys().collect();
+    databases.sort();
+
+    let mut objects: Vec<Object> = roles.into_iter().cloned().map(Object::Role).collect();
+
+    for database in databases {
+        objects.push(Object::Database(database.clone()));
+
+        for schema in &instance_setting.databases[database].schemas {
+            objects.push(Object::Schema(database.clone(), schema.clone()));
+        }
+    }
+
+    objects
+}
+
+// adds every declared object to the plan of an instance that doesn't exist yet
+pub fn plan_new(plan: &mut Plan, instance_setting: &InstanceSettings) {
+    for object in desired_objects(instance_setting) {
+        plan.push(Action::Add, object.resource(), vec![]);
+    }
+}
+
+// adds the declared objects missing from the instance to the plan, grants aren't compared since
+// they are applied again on every apply
+pub fn diff(
+    runtime: &dyn ContainerRuntime,
+    plan: &mut Plan,
+    instance_setting: &InstanceSettings,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+    let mut missing_databases: Vec<String> = vec![];
+
+    for object in desired_objects(instance_setting) {
+        let exists = match &object {
+            Object::Role(name) => role_exists(runtime, instance_name, name)?,
+            Object::Database(name) => {
+                let exists = database_exists(runtime, instance_name, name)?;
+                if !exists {
+                    missing_databases.push(name.clone());
+                }
+                exists
+            }
+            Object::Schema(database, _) if missing_databases.contains(database) => false,
+            Object::Schema(database, name) => {
+                schema_exists(runtime, instance_name, database, name)?
+            }
+        };
+
+        if !exists {
+            plan.push(Action::Add, object.resource(), vec![]);
+        }
+    }
+
+    Ok(())
+}
+
+// creates the declared roles, databases and schemas, then applies grants and default privileges
+pub fn reconcile(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+
+    for object in desired_objects(instance_setting) {
+        match &object {
+            Object::Role(name) => {
+                let role = &instance_setting.roles[name];
+
+                let login = if role.login { "login" } else { "nologin" };
+
+                if role_exists(runtime, instance_name, name)? {
+                    Docker::psql_query(
+                        runtime,
+                        instance_name,
+                        &format!("alter role {} {}", quote_identifier(name), login),
+                    )?;
+                    continue;
+                }
+
+                // the password is only set on creation, changing it is left to the user
+                match &role.password_env {
+                    Some(password_env) => {
+                        let password = env::var(password_env).with_context(|| {
+                            format!(
+                                "Environment variable {} with the password of role {} is not set",
+                                password_env, name
+                            )
+                        })?;
+
+                        Docker::psql_script(
+                            runtime,
+                            instance_name,
+                            &create_role_statement(name, login, Some(&password)),
+                        )?;
+                    }
+                    None => {
+                        Docker::psql_query(
+                            runtime,
+                            instance_name,
+                            &create_role_statement(name, login, None),
+                        )?;
+                    }
+                }
+                info!("- Role {} created on {}", name, instance_name);
+            }
+            Object::Database(name) => {
+                let owner = &instance_setting.databases[name].owner;
+
+                if !database_exists(runtime, instance_name, name)? {
+                    let mut create = format!("create database {}", quote_identifier(name));
+                    if let Some(owner) = owner {
+                        create.push_str(&format!(" owner {}", quote_identifier(owner)));
+                    }
+
+                    Docker::psql_query(runtime, instance_name, &create)?;
+                    info!("- Database {} created on {}", name, instance_name);
+                } else if let Some(owner) = owner {
+                    Docker::psql_query(
+                        runtime,
+                        instance_name,
+                        &format!(
+                            "alter database {} owner to {}",
+                            quote_identifier(name),
+                            quote_identifier(owner)
+                        ),
+                    )?;
+                }
+            }
+            Object::Schema(database, name) => {
+                // schemas belong to the owner of their database
+                let mut create = format!("create schema if not exists {}", quote_identifier(name));
+                if let Some(owner) = &instance_setting.databases[database].owner {
+                    create.push_str(&format!(" authorization {}", quote_identifier(owner)));
+                }
+
+                Docker::psql_query_database(runtime, instance_name, database, &create)?;
+            }
+        }
+    }
+
+    let mut roles: Vec<&String> = instance_setting.roles.keys().collect();
+    roles.sort();
+
+    for role in roles {
+        for grant in &instance_setting.roles[role].grants {
+            Docker::psql_query_database(
+                runtime,
+                instance_name,
+                &grant.database,
+                &grant_statement(role, grant)?,
+            )?;
+        }
+    }
+
+    for default_privilege in &instance_setting.default_privileges {
+        Docker::psql_query_database(
+            runtime,
+            instance_name,
+            &default_privilege.database,
+            &default_privilege_statement(default_privilege)?,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn database_exists(
+    runtime: &dyn ContainerRuntime,
+    instance_name: &str,
+    database: &str,
+) -> Result<bool> {
+    let found = Docker::psql_query(
+        runtime,
+        instance_name,
+        &format!(
+            "select 1 from pg_database where datname = {}",
+            quote_literal(database)
+        ),
+    )?;
+
+    Ok(!found.trim().is_empty())
+}
+
+fn role_exists(runtime: &dyn ContainerRuntime, instance_name: &str, role: &str) -> Result<bool> {
+    let found = Docker::psql_query(
+        runtime,
+        instance_name,
+        &format!(
+            "select 1 from pg_roles where rolname = {}",
+            quote_literal(role)
+        ),
+    )?;
+
+    Ok(!found.trim().is_empty())
+}
+
+fn schema_exists(
+    runtime: &dyn ContainerRuntime,
+    instance_name: &str,
+    database: &str,
+    schema: &str,
+) -> Result<bool> {
+    let found = Docker::psql_query_database(
+        runtime,
+        instance_name,
+        database,
+        &format!(
+            "select 1 from pg_namespace where nspname = {}",
+            quote_literal(schema)
+        ),
+    )?;
+
+    Ok(!found.trim().is_empty())
+}
+
+fn create_role_statement(name: &str, login: &str, password: Option<&str>) -> String {
+    let mut create = format!("create role {} {}", quote_identifier(name), login);
+    if let Some(password) = password {
+        create.push_str(&format!(" password {}", quote_literal(password)));
+    }
+
+    create
+}
+
+// privileges are spliced into sql, so only known keywords are accepted
+fn privilege_list(privileges: &[String]) -> Result<String> {
+    if privileges.is_empty() {
+        bail!("A grant must list at least one privilege");
+    }
+
+    let privileges: Vec<String> = privileges.iter().map(|p| p.trim().to_uppercase()).collect();
+
+    if let Some(unknown) = privileges
+        .iter()
+        .find(|p| !PRIVILEGES.contains(&p.as_str()))
+    {
+        bail!(
+            "Unknown privilege {}, expected one of: {}",
+            unknown,
+            PRIVILEGES.join(", ")
+        );
+    }
+
+    Ok(privileges.join(", "))
+}
+
+fn grant_statement(role: &str, grant: &Grant) -> Result<String> {
+    let privileges = privilege_list(&grant.privileges)?;
+    let on = grant.on.as_deref().unwrap_or(match grant.schema {
+        Some(_) => "tables",
+        None => "database",
+    });
+
+    let target = match (on, &grant.schema) {
+        ("database", _) => format!("database {}", quote_identifier(&grant.database)),
+        ("schema", Some(schema)) => format!("schema {}", quote_identifier(schema)),
+        ("tables" | "sequences" | "functions", Some(schema)) => {
+            format!("all {} in schema {}", on, quote_identifier(schema))
+        }
+        (_, None) => bail!(
+            "The grant on {} for role {} needs a schema",
+            on,
+            role
+        ),
+        (on, _) => bail!(
+            "Unknown grant target {} for role {}, expected database, schema, tables, sequences or functions",
+            on,
+            role
+        ),
+    };
+
+    Ok(format!(
+        "grant {} on {} to {}",
+        privileges,
+        target,
+        quote_identifier(role)
+    ))
+}
+
+fn default_privilege_statement(default_privilege: &DefaultPrivilege) -> Result<String> {
+    let privileges = privilege_list(&default_privilege.privileges)?;
+
+    if !DEFAULT_PRIVILEGE_TARGETS.contains(&default_privilege.on.as_str()) {
+        bail!(
+            "Unknown default privilege target {}, expected tables, sequences or functions",
+            default_privilege.on
+        );
+    }
+
+    let for_role = match &default_privilege.for_role {
+        Some(role) => format!(" for role {}", quote_identifier(role)),
+        None => String::new(),
+    };
+
+    Ok(format!(
+        "alter default privileges{} in schema {} grant {} on {} to {}",
+        for_role,
+        quote_identifier(&default_privilege.schema),
+        privileges,
+        default_privilege.on,
+        quote_identifier(&default_privilege.role)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::container_runtime::{ExecOutput, MockContainerRuntime};
+    use crate::cmd::apply::parse_instance_settings;
+
+    #[test]
+    fn statements_test() {
+        let contents = r#"
+[app.databases.app]
+owner = "app_owner"
+schemas = ["reporting"]
+
+[app.roles.app_owner]
+login = true
+password_env = "APP_OWNER_PASSWORD"
+
+[app.roles.reader]
+grants = [
+  { database = "app", privileges = ["connect"] },
+  { database = "app", schema = "reporting", privileges = ["select"] },
+  { database = "app", schema = "reporting", on = "schema", privileges = ["usage"] },
+]
+
+[[app.default_privileges]]
+database = "app"
+schema = "reporting"
+role = "reader"
+for_role = "app_owner"
+privileges = ["select"]
+"#;
+
+        let instance_settings = parse_instance_settings(contents).unwrap();
+        let app = &instance_settings["app"];
+
+        assert_eq!(
+            desired_objects(app),
+            vec![
+                Object::Role(String::from("app_owner")),
+                Object::Role(String::from("reader")),
+                Object::Database(String::from("app")),
+                Object::Schema(String::from("app"), String::from("reporting")),
+            ]
+        );
+
+        let grants: Vec<String> = app.roles["reader"]
+            .grants
+            .iter()
+            .map(|g| grant_statement("reader", g).unwrap())
+            .collect();
+        assert_eq!(
+            grants,
+            vec![
+                "grant CONNECT on database \"app\" to \"reader\"",
+                "grant SELECT on all tables in schema \"reporting\" to \"reader\"",
+                "grant USAGE on schema \"reporting\" to \"reader\"",
+            ]
+        );
+
+        assert_eq!(
+            default_privilege_statement(&app.default_privileges[0]).unwrap(),
+            "alter default privileges for role \"app_owner\" in schema \"reporting\" grant SELECT on tables to \"reader\""
+        );
+
+        let injected = Grant {
+            database: String::from("app"),
+            schema: None,
+            on: None,
+            privileges: vec![String::from("connect to public; drop table x")],
+        };
+        assert!(grant_statement("reader", &injected).is_err());
+    }
+
+    #[test]
+    fn create_role_password_test() {
+        let contents = r#"
+[app.roles.app_owner]
+login = true
+password_env = "TOPOLOGY_TEST_PASSWORD"
+"#;
+        let instance_settings = parse_instance_settings(contents).unwrap();
+        env::set_var("TOPOLOGY_TEST_PASSWORD", "s3cret");
+
+        let mut runtime = MockContainerRuntime::new();
+        runtime.expect_exec().returning(|_, cmd| {
+            // the password reaches psql in a file, never as an argument
+            if cmd[0] == "psql" {
+                assert!(!cmd.iter().any(|c| c.contains("s3cret")));
+            }
+
+            Ok(ExecOutput {
+                exit_code: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+            })
+        });
+
+        reconcile(&runtime, &instance_settings["app"]).unwrap();
+
+        assert_eq!(
+            create_role_statement("app_owner", "login", Some("it's")),
+            "create role \"app_owner\" login password 'it''s'"
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! Client for the Trunk registry, where extensions are published along with the Postgres versions
+//! they are built for and the libraries they need loaded
+
+use crate::Result;
+use anyhow::{bail, Context};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+pub const DEFAULT_REGISTRY_URL: &str = "https://registry.pgtrunk.io";
+// points the CLI at another registry, e.g. a mirror or a local stub
+pub const REGISTRY_ENV_VAR: &str = "TEMBO_TRUNK_REGISTRY";
+
+pub struct TrunkRegistry {
+    client: Client,
+    base_url: String,
+}
+
+// one published version of a Trunk project, only the fields the CLI uses
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TrunkProject {
+    pub name: String,
+    pub description: Option<String>,
+    pub version: String,
+    #[serde(default)]
+    pub extensions: Vec<TrunkExtension>,
+    #[serde(default)]
+    pub downloads: Vec<TrunkDownload>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TrunkExtension {
+    pub extension_name: String,
+    pub version: String,
+    #[serde(default)]
+    pub dependencies_extension_names: Option<Vec<String>>,
+    #[serde(default)]
+    pub loadable_libraries: Option<Vec<LoadableLibrary>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct LoadableLibrary {
+    pub library_name: String,
+    // the library must be in shared_preload_libraries, which takes a restart to change
+    pub requires_restart: bool,
+    pub priority: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TrunkDownload {
+    pub link: String,
+    pub pg_version: u8,
+    pub platform: Option<String>,
+}
+
+impl TrunkProject {
+    // the major Postgres versions the project is built for, sorted
+    pub fn pg_versions(&self) -> Vec<u8> {
+        let mut versions: Vec<u8> = self.downloads.iter().map(|d| d.pg_version).collect();
+        versions.sort();
+        versions.dedup();
+
+        versions
+    }
+
+    // the extensions the project's extensions depend on, outside the project itself
+    pub fn dependencies(&self) -> Vec<String> {
+        let mut dependencies: Vec<String> = self
+            .extensions
+            .iter()
+            .flat_map(|e| e.dependencies_extension_names.iter().flatten())
+            .filter(|d| !self.extensions.iter().any(|e| &e.extension_name == *d))
+            .cloned()
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+
+        dependencies
+    }
+
+    // the version the package creates the extension at, e.g. 1.6 for pg_cron 1.6.2, the only
+    // extension's when none is named like it
+    pub fn extension_version(&self, name: &str) -> Option<String> {
+        self.extensions
+            .iter()
+            .find(|e| e.extension_name == name)
+            .or(match self.extensions.as_slice() {
+                [only] => Some(only),
+                _ => None,
+            })
+            .map(|e| e.version.clone())
+    }
+
+    // the libraries that must be in shared_preload_libraries, in load order
+    pub fn preload_libraries(&self) -> Vec<String> {
+        let mut libraries: Vec<&LoadableLibrary> = self
+            .extensions
+            .iter()
+            .flat_map(|e| e.loadable_libraries.iter().flatten())
+            .filter(|l| l.requires_restart)
+            .collect();
+        libraries.sort_by_key(|l| l.priority.unwrap_or(i32::MAX));
+
+        let mut names: Vec<String> = vec![];
+        for library in libraries {
+            if !names.contains(&library.library_name) {
+                names.push(library.library_name.clone());
+            }
+        }
+
+        names
+    }
+}
+
+impl TrunkRegistry {
+    pub fn new(base_url: &str) -> TrunkRegistry {
+        TrunkRegistry {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    // the registry set by TEMBO_TRUNK_REGISTRY, the public one otherwise
+    pub fn from_env() -> TrunkRegistry {
+        let base_url = env::var(REGISTRY_ENV_VAR)
+            .ok()
+            .filter(|u| !u.is_empty())
+            .unwrap_or_else(|| DEFAULT_REGISTRY_URL.to_string());
+
+        TrunkRegistry::new(&base_url)
+    }
+
+    fn projects_url(&self) -> String {
+        format!("{}/api/v1/trunk-projects", self.base_url)
+    }
+
+    // the latest version of every project whose name or description contains the term
+    pub fn search(&self, term: &str) -> Result<Vec<TrunkProject>> {
+        let res = self
+            .client
+            .get(self.projects_url())
+            .send()
+            .with_context(|| format!("Unable to reach the Trunk registry at {}", self.base_url))?;
+
+        if !res.status().is_success() {
+            bail!("Trunk registry returned {}", res.status());
+        }
+
+        let projects: Vec<TrunkProject> = res
+            .json()
+            .with_context(|| "Unable to parse projects from the Trunk registry")?;
+        let term = term.to_lowercase();
+
+        let mut found: Vec<TrunkProject> = projects
+            .into_iter()
+            .filter(|p| {
+                p.name.to_lowercase().contains(&term)
+                    || p.description
+                        .as_deref()
+                        .unwrap_or_default()
+                        .to_lowercase()
+                        .contains(&term)
+            })
+            .collect();
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(found)
+    }
+
+    // every published version of the project, newest first, empty when there is no such project
+    pub fn versions(&self, name: &str) -> Result<Vec<TrunkProject>> {
+        let res = self
+            .client
+            .get(format!("{}/{}", self.projects_url(), name))
+            .send()
+            .with_context(|| format!("Unable to reach the Trunk registry at {}", self.base_url))?;
+
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        if !res.status().is_success() {
+            bail!("Trunk registry returned {}", res.status());
+        }
+
+        let mut versions: Vec<TrunkProject> = res
+            .json()
+            .with_context(|| format!("Unable to parse project {} from the Trunk registry", name))?;
+        versions.sort_by(
+            |a, b| match (Version::parse(&a.version), Version::parse(&b.version)) {
+                (Ok(a), Ok(b)) => b.cmp(&a),
+                _ => b.version.cmp(&a.version),
+            },
+        );
+
+        Ok(versions)
+    }
+
+    // fails unless the project is published, at the version when one is given
+    pub fn check_project(&self, name: &str, version: Option<&str>) -> Result<TrunkProject> {
+        select_version(name, &self.versions(name)?, version)
+    }
+}
+
+// picks the version from those published, the latest when none is given
+pub fn select_version(
+    name: &str,
+    versions: &[TrunkProject],
+    version: Option<&str>,
+) -> Result<TrunkProject> {
+    let project = match version {
+        Some(version) => versions.iter().find(|p| p.version == version),
+        None => versions.first(),
+    };
+
+    match (project, version) {
+        (Some(project), _) => Ok(project.clone()),
+        (None, _) if versions.is_empty() => bail!(
+            "Extension {} was not found in the Trunk registry, try `tembo extension search {}`",
+            name,
+            name
+        ),
+        (None, version) => bail!(
+            "Version {} of {} was not found in the Trunk registry, available versions: {}",
+            version.unwrap_or_default(),
+            name,
+            versions
+                .iter()
+                .map(|p| p.version.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const PROJECTS: &str = r#"[
+        {"name": "pgmq", "description": "A lightweight message queue", "version": "1.1.1",
+         "extensions": [{"extension_name": "pgmq", "version": "1.1.1",
+                         "dependencies_extension_names": ["pg_partman"]}],
+         "downloads": [{"link": "https://example.com/pgmq-15.tar.gz", "pg_version": 15},
+                       {"link": "https://example.com/pgmq-14.tar.gz", "pg_version": 14}]},
+        {"name": "pg_cron", "description": "Job scheduler", "version": "1.6.2",
+         "extensions": [{"extension_name": "pg_cron", "version": "1.6",
+                         "loadable_libraries": [{"library_name": "pg_cron", "requires_restart": true, "priority": 1}]}]}
+    ]"#;
+
+    const PGMQ_VERSIONS: &str = r#"[
+        {"name": "pgmq", "version": "0.33.1"},
+        {"name": "pgmq", "version": "1.1.1"}
+    ]"#;
+
+    // serves canned responses by path until the test process exits
+    fn stub_registry() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buffer = [0; 4096];
+                let read = stream.read(&mut buffer).unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]);
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+                let (status, body) = match path {
+                    "/api/v1/trunk-projects" => ("200 OK", PROJECTS),
+                    "/api/v1/trunk-projects/pgmq" => ("200 OK", PGMQ_VERSIONS),
+                    _ => ("404 Not Found", ""),
+                };
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        format!("http://{}/", address)
+    }
+
+    #[test]
+    fn registry_test() {
+        let registry = TrunkRegistry::new(&stub_registry());
+
+        let found = registry.search("QUEUE").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "pgmq");
+        assert_eq!(found[0].pg_versions(), vec![14, 15]);
+        assert_eq!(found[0].dependencies(), vec![String::from("pg_partman")]);
+
+        let cron = &registry.search("cron").unwrap()[0];
+        assert_eq!(cron.preload_libraries(), vec![String::from("pg_cron")]);
+        assert_eq!(cron.extension_version("pg_cron"), Some(String::from("1.6")));
+
+        let versions = registry.versions("pgmq").unwrap();
+        assert_eq!(versions[0].version, "1.1.1");
+
+        assert_eq!(
+            registry.check_project("pgmq", None).unwrap().version,
+            "1.1.1"
+        );
+        assert!(registry.check_project("pgmq", Some("0.33.1")).is_ok());
+        assert!(registry.check_project("pgmq", Some("9.9.9")).is_err());
+        assert!(registry.check_project("pgmqq", None).is_err());
+    }
+}
@@ -0,0 +1,1383 @@
+//! Validation of tembo.toml, reporting problems as diagnostics with file:line:column locations
+//! (so mistakes are caught before anything is built locally or sent to Tembo Cloud)
+
+use crate::cli::stacks::{define_stacks, Stacks};
+use crate::cli::tembo_config::{TemboConfig, DEFAULTS_KEY, PG_VERSIONS, VERSION_KEY};
+use crate::cli::topology::{DEFAULT_PRIVILEGE_TARGETS, GRANT_TARGETS, PRIVILEGES};
+use semver::Version;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Range;
+use toml::{Spanned, Value};
+
+pub const MIN_REPLICAS: i64 = 1;
+pub const MAX_REPLICAS: i64 = 5;
+
+// fields an instance table in tembo.toml may contain
+const INSTANCE_FIELDS: &[&str] = &[
+    "port",
+    "environment",
+    "stack",
+    "stack_type",
+    "pg_version",
+    "template_dir",
+    "cpu",
+    "memory",
+    "storage",
+    "replicas",
+    "postgres_configurations",
+    "extensions",
+    "databases",
+    "roles",
+    "default_privileges",
+];
+
+const EXTENSION_FIELDS: &[&str] = &[
+    "enabled",
+    "trunk_project",
+    "trunk_project_version",
+    "extension_version",
+    "locations",
+];
+
+const LOCATION_FIELDS: &[&str] = &["database", "schema", "version"];
+
+const DATABASE_FIELDS: &[&str] = &["owner", "schemas"];
+
+const ROLE_FIELDS: &[&str] = &["login", "password_env", "grants"];
+
+const GRANT_FIELDS: &[&str] = &["database", "schema", "on", "privileges"];
+
+const DEFAULT_PRIVILEGE_FIELDS: &[&str] =
+    &["database", "schema", "role", "for_role", "on", "privileges"];
+
+// the longest database or schema name postgres accepts, in bytes
+const MAX_IDENTIFIER_LENGTH: usize = 63;
+
+const MEMORY_UNITS: &[&str] = &["Ki", "Mi", "Gi", "Ti"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GucType {
+    Bool,
+    Integer,
+    Real,
+    Memory,
+    Time,
+    String,
+}
+
+// commonly tuned Postgres settings and the kind of value each expects
+const KNOWN_POSTGRES_CONFIGS: &[(&str, GucType)] = &[
+    ("autovacuum", GucType::Bool),
+    ("autovacuum_max_workers", GucType::Integer),
+    ("autovacuum_naptime", GucType::Time),
+    ("autovacuum_vacuum_scale_factor", GucType::Real),
+    ("autovacuum_analyze_scale_factor", GucType::Real),
+    ("checkpoint_completion_target", GucType::Real),
+    ("checkpoint_timeout", GucType::Time),
+    ("default_statistics_target", GucType::Integer),
+    ("effective_cache_size", GucType::Memory),
+    ("effective_io_concurrency", GucType::Integer),
+    ("idle_in_transaction_session_timeout", GucType::Time),
+    ("jit", GucType::Bool),
+    ("listen_addresses", GucType::String),
+    ("lock_timeout", GucType::Time),
+    ("log_min_duration_statement", GucType::Time),
+    ("log_statement", GucType::String),
+    ("maintenance_work_mem", GucType::Memory),
+    ("max_connections", GucType::Integer),
+    ("max_parallel_maintenance_workers", GucType::Integer),
+    ("max_parallel_workers", GucType::Integer),
+    ("max_parallel_workers_per_gather", GucType::Integer),
+    ("max_replication_slots", GucType::Integer),
+    ("max_wal_senders", GucType::Integer),
+    ("max_wal_size", GucType::Memory),
+    ("max_worker_processes", GucType::Integer),
+    ("min_wal_size", GucType::Memory),
+    ("random_page_cost", GucType::Real),
+    ("search_path", GucType::String),
+    ("seq_page_cost", GucType::Real),
+    ("shared_buffers", GucType::Memory),
+    ("shared_preload_libraries", GucType::String),
+    ("statement_timeout", GucType::Time),
+    ("temp_buffers", GucType::Memory),
+    ("wal_buffers", GucType::Memory),
+    ("wal_level", GucType::String),
+    ("work_mem", GucType::Memory),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file, self.line, self.column, severity, self.message
+        )?;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n    help: {}", suggestion)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+// mirrors an instance table of tembo.toml, keeping the location of each value
+#[derive(Deserialize)]
+struct RawInstance {
+    port: Option<Spanned<Value>>,
+    stack: Option<Spanned<Value>>,
+    pg_version: Option<Spanned<Value>>,
+    cpu: Option<Spanned<Value>>,
+    memory: Option<Spanned<Value>>,
+    storage: Option<Spanned<Value>>,
+    replicas: Option<Spanned<Value>>,
+    postgres_configurations: Option<BTreeMap<Spanned<String>, Spanned<Value>>>,
+    extensions: Option<BTreeMap<Spanned<String>, RawExtension>>,
+    databases: Option<BTreeMap<Spanned<String>, RawDatabase>>,
+    roles: Option<BTreeMap<Spanned<String>, RawRole>>,
+    default_privileges: Option<Vec<Spanned<RawDefaultPrivilege>>>,
+}
+
+#[derive(Deserialize)]
+struct RawDatabase {
+    owner: Option<Spanned<String>>,
+    schemas: Option<Vec<Spanned<String>>>,
+}
+
+#[derive(Deserialize)]
+struct RawRole {
+    login: Option<Spanned<Value>>,
+    password_env: Option<Spanned<String>>,
+    grants: Option<Vec<Spanned<RawGrant>>>,
+}
+
+#[derive(Deserialize)]
+struct RawGrant {
+    database: Option<Spanned<String>>,
+    schema: Option<Spanned<String>>,
+    on: Option<Spanned<String>>,
+    privileges: Option<Vec<Spanned<String>>>,
+}
+
+#[derive(Deserialize)]
+struct RawDefaultPrivilege {
+    database: Option<Spanned<String>>,
+    schema: Option<Spanned<String>>,
+    role: Option<Spanned<String>>,
+    for_role: Option<Spanned<String>>,
+    on: Option<Spanned<String>>,
+    privileges: Option<Vec<Spanned<String>>>,
+}
+
+#[derive(Deserialize)]
+struct RawExtension {
+    enabled: Option<Spanned<Value>>,
+    trunk_project: Option<Spanned<String>>,
+    trunk_project_version: Option<Spanned<String>>,
+    extension_version: Option<Spanned<String>>,
+    locations: Option<Vec<RawExtensionLocation>>,
+}
+
+#[derive(Deserialize)]
+struct RawExtensionLocation {
+    database: Option<Spanned<String>>,
+    schema: Option<Spanned<String>>,
+}
+
+// the keys of each extension table, used to flag unknown fields
+#[derive(Deserialize)]
+struct RawExtensionKeys {
+    #[serde(default)]
+    extensions: BTreeMap<String, BTreeMap<Spanned<String>, Value>>,
+}
+
+// the keys of each extension location, used to flag unknown fields
+#[derive(Deserialize)]
+struct RawLocationKeys {
+    #[serde(default)]
+    extensions: BTreeMap<String, RawExtensionLocationKeys>,
+}
+
+#[derive(Deserialize)]
+struct RawExtensionLocationKeys {
+    #[serde(default)]
+    locations: Vec<BTreeMap<Spanned<String>, Value>>,
+}
+
+// the keys of databases, roles and default privileges, used to flag unknown fields
+#[derive(Deserialize)]
+struct RawTopologyKeys {
+    #[serde(default)]
+    databases: BTreeMap<String, BTreeMap<Spanned<String>, Value>>,
+    #[serde(default)]
+    roles: BTreeMap<String, BTreeMap<Spanned<String>, Value>>,
+    #[serde(default)]
+    default_privileges: Vec<BTreeMap<Spanned<String>, Value>>,
+}
+
+// the keys of each grant of a role, used to flag unknown fields
+#[derive(Deserialize)]
+struct RawGrantKeys {
+    #[serde(default)]
+    roles: BTreeMap<String, RawRoleGrantKeys>,
+}
+
+#[derive(Deserialize)]
+struct RawRoleGrantKeys {
+    #[serde(default)]
+    grants: Vec<BTreeMap<Spanned<String>, Value>>,
+}
+
+// a top level entry of tembo.toml, a table for `[defaults]` and instances or a scalar for `version`
+enum TableOr<T> {
+    Table(T),
+    Scalar(Value),
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for TableOr<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TableOrVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for TableOrVisitor<T> {
+            type Value = TableOr<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a table or a value")
+            }
+
+            // the map is handed over as is so spans inside the table are kept
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<Self::Value, M::Error> {
+                T::deserialize(MapAccessDeserializer::new(map)).map(TableOr::Table)
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(TableOr::Scalar(Value::String(v.to_string())))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(TableOr::Scalar(Value::Integer(v)))
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(TableOr::Scalar(Value::Float(v)))
+            }
+
+            fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(TableOr::Scalar(Value::Boolean(v)))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut values = vec![];
+                while let Some(value) = seq.next_element()? {
+                    values.push(value);
+                }
+
+                Ok(TableOr::Scalar(Value::Array(values)))
+            }
+        }
+
+        deserializer.deserialize_any(TableOrVisitor(PhantomData))
+    }
+}
+
+struct Validator<'a> {
+    file: &'a str,
+    contents: &'a str,
+    diagnostics: Vec<Diagnostic>,
+    // extensions `[defaults]` configures, to point at when an instance inherits a problem
+    default_extensions: BTreeMap<String, Range<usize>>,
+    // loaded once an instance names a stack
+    stacks: Option<Stacks>,
+}
+
+// validates the contents of a tembo.toml file, returning every problem found
+pub fn validate(file: &str, contents: &str) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        file,
+        contents,
+        diagnostics: vec![],
+        default_extensions: BTreeMap::new(),
+        stacks: None,
+    };
+
+    validator.validate();
+    validator.diagnostics
+}
+
+impl<'a> Validator<'a> {
+    fn push(
+        &mut self,
+        severity: Severity,
+        span: Range<usize>,
+        message: String,
+        suggestion: Option<String>,
+    ) {
+        let (line, column) = line_column(self.contents, span.start);
+
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file: self.file.to_string(),
+            line,
+            column,
+            message,
+            suggestion,
+        });
+    }
+
+    fn error(&mut self, span: Range<usize>, message: String, suggestion: Option<String>) {
+        self.push(Severity::Error, span, message, suggestion);
+    }
+
+    fn warning(&mut self, span: Range<usize>, message: String, suggestion: Option<String>) {
+        self.push(Severity::Warning, span, message, suggestion);
+    }
+
+    fn validate(&mut self) {
+        // entries aren't spanned themselves, as tables implied by a dotted header have no span
+        let entries: BTreeMap<Spanned<String>, TableOr<RawInstance>> =
+            match toml::from_str(self.contents) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    let span = e.span().unwrap_or(0..0);
+                    self.error(span, e.message().to_string(), None);
+                    return;
+                }
+            };
+
+        self.validate_field_names();
+
+        if let Some(TableOr::Table(defaults)) = entries
+            .iter()
+            .find(|(name, _)| name.get_ref() == DEFAULTS_KEY)
+            .map(|(_, entry)| entry)
+        {
+            self.default_extensions = defaults
+                .extensions
+                .iter()
+                .flatten()
+                .map(|(name, _)| (name.get_ref().clone(), name.span()))
+                .collect();
+        }
+
+        let config = TemboConfig::parse(self.contents).ok();
+
+        for (name, entry) in entries.iter() {
+            match (name.get_ref().as_str(), entry) {
+                (VERSION_KEY, TableOr::Scalar(Value::String(_))) => {}
+                (VERSION_KEY, _) => self.error(
+                    name.span(),
+                    format!("`{}` must be a string", VERSION_KEY),
+                    Some(String::from("use version = \"1.0\"")),
+                ),
+                (DEFAULTS_KEY, TableOr::Table(defaults)) => self.validate_settings(defaults),
+                (_, TableOr::Table(instance)) => {
+                    self.validate_instance_name(name);
+                    self.validate_settings(instance);
+                    if let Some(config) = &config {
+                        self.validate_merged(config, name, instance);
+                    }
+                }
+                (_, TableOr::Scalar(_)) => self.error(
+                    name.span(),
+                    format!("`{}` must be a table", name.get_ref()),
+                    Some(format!("declare the instance with [{}]", name.get_ref())),
+                ),
+            }
+        }
+    }
+
+    // flags unknown fields in instance and extension tables, suggesting the closest known one
+    fn validate_field_names(&mut self) {
+        type Fields = TableOr<BTreeMap<Spanned<String>, Value>>;
+
+        let instances: BTreeMap<String, Fields> = match toml::from_str(self.contents) {
+            Ok(instances) => instances,
+            Err(_) => return,
+        };
+
+        for fields in instances.values() {
+            if let TableOr::Table(fields) = fields {
+                for key in fields.keys() {
+                    self.validate_field_name(key, INSTANCE_FIELDS, "instance");
+                }
+            }
+        }
+
+        let extensions: BTreeMap<String, TableOr<RawExtensionKeys>> =
+            match toml::from_str(self.contents) {
+                Ok(extensions) => extensions,
+                Err(_) => return,
+            };
+
+        for instance in extensions.values() {
+            if let TableOr::Table(instance) = instance {
+                for fields in instance.extensions.values() {
+                    for key in fields.keys() {
+                        self.validate_field_name(key, EXTENSION_FIELDS, "extension");
+                    }
+                }
+            }
+        }
+
+        let locations: BTreeMap<String, TableOr<RawLocationKeys>> =
+            match toml::from_str(self.contents) {
+                Ok(locations) => locations,
+                Err(_) => return,
+            };
+
+        for instance in locations.values() {
+            if let TableOr::Table(instance) = instance {
+                for extension in instance.extensions.values() {
+                    for fields in &extension.locations {
+                        for key in fields.keys() {
+                            self.validate_field_name(key, LOCATION_FIELDS, "extension location");
+                        }
+                    }
+                }
+            }
+        }
+
+        let topology: BTreeMap<String, TableOr<RawTopologyKeys>> =
+            match toml::from_str(self.contents) {
+                Ok(topology) => topology,
+                Err(_) => return,
+            };
+
+        for instance in topology.values() {
+            if let TableOr::Table(instance) = instance {
+                for key in instance.databases.values().flat_map(|d| d.keys()) {
+                    self.validate_field_name(key, DATABASE_FIELDS, "database");
+                }
+                for key in instance.roles.values().flat_map(|r| r.keys()) {
+                    self.validate_field_name(key, ROLE_FIELDS, "role");
+                }
+                for key in instance.default_privileges.iter().flat_map(|d| d.keys()) {
+                    self.validate_field_name(key, DEFAULT_PRIVILEGE_FIELDS, "default privilege");
+                }
+            }
+        }
+
+        let grants: BTreeMap<String, TableOr<RawGrantKeys>> = match toml::from_str(self.contents) {
+            Ok(grants) => grants,
+            Err(_) => return,
+        };
+
+        for instance in grants.values() {
+            if let TableOr::Table(instance) = instance {
+                for role in instance.roles.values() {
+                    for key in role.grants.iter().flat_map(|g| g.keys()) {
+                        self.validate_field_name(key, GRANT_FIELDS, "grant");
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_field_name(&mut self, key: &Spanned<String>, known: &[&str], kind: &str) {
+        if known.contains(&key.get_ref().as_str()) {
+            return;
+        }
+
+        self.error(
+            key.span(),
+            format!("unknown {} field `{}`", kind, key.get_ref()),
+            closest(key.get_ref(), known).map(|c| format!("did you mean `{}`?", c)),
+        );
+    }
+
+    fn validate_instance_name(&mut self, name: &Spanned<String>) {
+        if name.get_ref().is_empty()
+            || !name
+                .get_ref()
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            self.error(
+                name.span(),
+                format!("invalid instance name `{}`", name.get_ref()),
+                Some(String::from(
+                    "use only letters, numbers, dashes and underscores",
+                )),
+            );
+        }
+    }
+
+    fn validate_settings(&mut self, instance: &RawInstance) {
+        if let Some(port) = &instance.port {
+            match port.get_ref().as_integer() {
+                Some(p) if (1..=65535).contains(&p) => {}
+                _ => self.error(
+                    port.span(),
+                    format!("invalid port `{}`", port.get_ref()),
+                    Some(String::from("use a number between 1 and 65535, e.g. 5432")),
+                ),
+            }
+        }
+
+        if let Some(pg_version) = &instance.pg_version {
+            match pg_version.get_ref().as_integer() {
+                Some(v) if PG_VERSIONS.iter().any(|p| i64::from(*p) == v) => {}
+                _ => self.error(
+                    pg_version.span(),
+                    format!("unsupported pg_version `{}`", pg_version.get_ref()),
+                    Some(format!(
+                        "use one of {}",
+                        PG_VERSIONS
+                            .iter()
+                            .map(|v| v.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )),
+                ),
+            }
+        }
+
+        if let Some(stack) = &instance.stack {
+            self.validate_stack(stack);
+        }
+
+        if let Some(cpu) = &instance.cpu {
+            self.validate_cpu(cpu);
+        }
+
+        if let Some(memory) = &instance.memory {
+            self.validate_quantity("memory", memory, "2Gi");
+        }
+
+        if let Some(storage) = &instance.storage {
+            self.validate_quantity("storage", storage, "10Gi");
+        }
+
+        if let Some(replicas) = &instance.replicas {
+            match replicas.get_ref().as_integer() {
+                Some(r) if (MIN_REPLICAS..=MAX_REPLICAS).contains(&r) => {}
+                _ => self.error(
+                    replicas.span(),
+                    format!("invalid replicas `{}`", replicas.get_ref()),
+                    Some(format!(
+                        "use a number between {} and {}",
+                        MIN_REPLICAS, MAX_REPLICAS
+                    )),
+                ),
+            }
+        }
+
+        if let Some(configs) = &instance.postgres_configurations {
+            for (key, value) in configs.iter() {
+                self.validate_postgres_config(key, value);
+            }
+        }
+
+        if let Some(extensions) = &instance.extensions {
+            for (key, extension) in extensions.iter() {
+                self.validate_extension(key, extension);
+            }
+        }
+
+        for (name, database) in instance.databases.iter().flatten() {
+            self.validate_name("database", name);
+
+            if let Some(owner) = &database.owner {
+                self.validate_name("role", owner);
+            }
+            for schema in database.schemas.iter().flatten() {
+                self.validate_name("schema", schema);
+            }
+        }
+
+        for (name, role) in instance.roles.iter().flatten() {
+            self.validate_role(name, role);
+        }
+
+        for default_privilege in instance.default_privileges.iter().flatten() {
+            self.validate_default_privilege(default_privilege);
+        }
+    }
+
+    fn validate_role(&mut self, name: &Spanned<String>, role: &RawRole) {
+        self.validate_name("role", name);
+
+        if let Some(login) = &role.login {
+            if !login.get_ref().is_bool() {
+                self.error(
+                    login.span(),
+                    format!("role `{}` has an invalid login", name.get_ref()),
+                    Some(String::from("use login = true or login = false")),
+                );
+            }
+        }
+
+        if let Some(password_env) = &role.password_env {
+            if password_env.get_ref().trim().is_empty() {
+                self.error(
+                    password_env.span(),
+                    format!("role `{}` has an empty password_env", name.get_ref()),
+                    Some(String::from(
+                        "name the environment variable holding the password",
+                    )),
+                );
+            }
+        }
+
+        for grant in role.grants.iter().flatten() {
+            let span = grant.span();
+            let grant = grant.get_ref();
+            let owner = format!("a grant of role `{}`", name.get_ref());
+
+            match &grant.database {
+                Some(database) => self.validate_name("database", database),
+                None => self.error(
+                    span.clone(),
+                    format!("{} is missing database", owner),
+                    Some(String::from("add database = \"postgres\"")),
+                ),
+            }
+
+            if let Some(schema) = &grant.schema {
+                self.validate_name("schema", schema);
+            }
+
+            if let Some(on) = &grant.on {
+                self.validate_target(&owner, on, GRANT_TARGETS);
+
+                if on.get_ref() != "database" && grant.schema.is_none() {
+                    self.error(
+                        on.span(),
+                        format!("{} on `{}` needs a schema", owner, on.get_ref()),
+                        Some(String::from("add schema = \"public\"")),
+                    );
+                }
+            }
+
+            self.validate_privileges(&owner, span, grant.privileges.as_deref());
+        }
+    }
+
+    fn validate_default_privilege(&mut self, default_privilege: &Spanned<RawDefaultPrivilege>) {
+        let span = default_privilege.span();
+        let default_privilege = default_privilege.get_ref();
+        let owner = String::from("a default privilege");
+
+        for (kind, name) in [
+            ("database", &default_privilege.database),
+            ("schema", &default_privilege.schema),
+            ("role", &default_privilege.role),
+        ] {
+            match name {
+                Some(name) => self.validate_name(kind, name),
+                None => self.error(span.clone(), format!("{} is missing {}", owner, kind), None),
+            }
+        }
+
+        if let Some(for_role) = &default_privilege.for_role {
+            self.validate_name("role", for_role);
+        }
+
+        if let Some(on) = &default_privilege.on {
+            self.validate_target(&owner, on, DEFAULT_PRIVILEGE_TARGETS);
+        }
+
+        self.validate_privileges(&owner, span, default_privilege.privileges.as_deref());
+    }
+
+    fn validate_target(&mut self, owner: &str, on: &Spanned<String>, targets: &[&str]) {
+        if !targets.contains(&on.get_ref().as_str()) {
+            self.error(
+                on.span(),
+                format!("{} has an unknown target `{}`", owner, on.get_ref()),
+                Some(format!("use one of {}", targets.join(", "))),
+            );
+        }
+    }
+
+    // privileges are spliced into sql by apply, so only known keywords pass
+    fn validate_privileges(
+        &mut self,
+        owner: &str,
+        span: Range<usize>,
+        privileges: Option<&[Spanned<String>]>,
+    ) {
+        let privileges = match privileges {
+            Some(privileges) if !privileges.is_empty() => privileges,
+            _ => {
+                self.error(
+                    span,
+                    format!("{} lists no privileges", owner),
+                    Some(String::from("add privileges = [\"select\"]")),
+                );
+                return;
+            }
+        };
+
+        for privilege in privileges {
+            let keyword = privilege.get_ref().trim().to_uppercase();
+
+            if !PRIVILEGES.contains(&keyword.as_str()) {
+                self.error(
+                    privilege.span(),
+                    format!(
+                        "{} has an unknown privilege `{}`",
+                        owner,
+                        privilege.get_ref()
+                    ),
+                    closest(&keyword, PRIVILEGES)
+                        .map(|c| format!("did you mean `{}`?", c.to_lowercase())),
+                );
+            }
+        }
+    }
+
+    // database, schema and role names must fit in a postgres identifier
+    fn validate_name(&mut self, kind: &str, name: &Spanned<String>) {
+        let length = name.get_ref().len();
+
+        if length == 0 || length > MAX_IDENTIFIER_LENGTH {
+            self.error(
+                name.span(),
+                format!("invalid {} name `{}`", kind, name.get_ref()),
+                Some(format!(
+                    "use a name of 1 to {} characters",
+                    MAX_IDENTIFIER_LENGTH
+                )),
+            );
+        }
+    }
+
+    fn validate_stack(&mut self, stack: &Spanned<Value>) {
+        let name = match stack.get_ref().as_str() {
+            Some(name) => name,
+            None => {
+                self.error(
+                    stack.span(),
+                    format!("invalid stack `{}`", stack.get_ref()),
+                    Some(String::from("use a stack name, e.g. \"Standard\"")),
+                );
+                return;
+            }
+        };
+
+        if self.stacks.is_none() {
+            self.stacks = define_stacks().ok();
+        }
+
+        if let Some(stacks) = &self.stacks {
+            if stacks.find(name).is_none() {
+                let names: Vec<&str> = stacks.stacks.iter().map(|s| s.name.as_str()).collect();
+
+                self.error(
+                    stack.span(),
+                    format!("stack `{}` is not defined", name),
+                    closest(name, &names)
+                        .map(|c| format!("did you mean `{}`?", c))
+                        .or(Some(String::from("see `tembo stack list`"))),
+                );
+            }
+        }
+    }
+
+    // checks the instance once `[defaults]` and its stack are applied, as an extension can get
+    // enabled or its trunk_project from either and only override the rest
+    fn validate_merged(
+        &mut self,
+        config: &TemboConfig,
+        name: &Spanned<String>,
+        instance: &RawInstance,
+    ) {
+        let merged = match config.merged_table(name.get_ref(), &mut self.stacks) {
+            Ok(merged) => merged,
+            Err(_) => return,
+        };
+
+        let extensions = match merged.get("extensions") {
+            Some(Value::Table(extensions)) => extensions,
+            _ => return,
+        };
+
+        for (extension, settings) in extensions {
+            let raw = instance
+                .extensions
+                .iter()
+                .flatten()
+                .find(|(key, _)| key.get_ref() == extension);
+            let span = raw
+                .map(|(key, _)| key.span())
+                .or_else(|| self.default_extensions.get(extension).cloned())
+                .unwrap_or_else(|| name.span());
+
+            if settings.get("enabled").is_none() {
+                self.merged_error(
+                    span.clone(),
+                    format!("extension `{}` is missing enabled", extension),
+                    String::from("add enabled = true"),
+                );
+            }
+
+            if settings.get("trunk_project").is_none()
+                && settings.get("trunk_project_version").is_some()
+            {
+                let span = raw
+                    .and_then(|(_, raw)| raw.trunk_project_version.as_ref())
+                    .map(|version| version.span())
+                    .unwrap_or(span);
+
+                self.merged_error(
+                    span,
+                    format!(
+                        "extension `{}` sets trunk_project_version without a trunk_project",
+                        extension
+                    ),
+                    format!("add trunk_project = \"{}\"", extension),
+                );
+            }
+        }
+    }
+
+    // an error found on the merged settings, reported once when several instances inherit it
+    // from `[defaults]`
+    fn merged_error(&mut self, span: Range<usize>, message: String, suggestion: String) {
+        let (line, column) = line_column(self.contents, span.start);
+
+        if !self
+            .diagnostics
+            .iter()
+            .any(|d| d.line == line && d.column == column && d.message == message)
+        {
+            self.error(span, message, Some(suggestion));
+        }
+    }
+
+    fn validate_cpu(&mut self, cpu: &Spanned<Value>) {
+        let valid = match cpu.get_ref().as_str() {
+            Some(s) => match s.strip_suffix('m') {
+                Some(millis) => millis.parse::<u32>().map(|m| m > 0).unwrap_or(false),
+                None => s.parse::<f64>().map(|c| c > 0.0).unwrap_or(false),
+            },
+            None => false,
+        };
+
+        if !valid {
+            let suggestion = match cpu.get_ref() {
+                Value::Integer(i) => format!("quote the value, e.g. \"{}\"", i),
+                Value::Float(f) => format!("quote the value, e.g. \"{}\"", f),
+                _ => String::from("use a number of cores such as \"1\" or \"0.5\", or millicores such as \"500m\""),
+            };
+
+            self.error(
+                cpu.span(),
+                format!("invalid cpu `{}`", cpu.get_ref()),
+                Some(suggestion),
+            );
+        }
+    }
+
+    fn validate_quantity(&mut self, field: &str, value: &Spanned<Value>, example: &str) {
+        let s = match value.get_ref().as_str() {
+            Some(s) => s,
+            None => {
+                self.error(
+                    value.span(),
+                    format!("invalid {} `{}`, expected a string", field, value.get_ref()),
+                    Some(format!("use a quantity such as \"{}\"", example)),
+                );
+                return;
+            }
+        };
+
+        let valid = MEMORY_UNITS.iter().any(|unit| {
+            s.strip_suffix(unit)
+                .map(|n| n.parse::<f64>().map(|n| n > 0.0).unwrap_or(false))
+                .unwrap_or(false)
+        });
+
+        if !valid {
+            // a common mistake is using GB/MB rather than Gi/Mi
+            let number: String = s
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            let unit = s[number.len()..].to_lowercase();
+            let suggestion = match unit.chars().next() {
+                Some(u @ ('k' | 'm' | 'g' | 't')) if !number.is_empty() => {
+                    format!("did you mean \"{}{}i\"?", number, u.to_ascii_uppercase())
+                }
+                _ => format!(
+                    "use a number followed by one of {}, e.g. \"{}\"",
+                    MEMORY_UNITS.join(", "),
+                    example
+                ),
+            };
+
+            self.error(
+                value.span(),
+                format!("invalid {} `{}`", field, s),
+                Some(suggestion),
+            );
+        }
+    }
+
+    fn validate_postgres_config(&mut self, key: &Spanned<String>, value: &Spanned<Value>) {
+        let name = key.get_ref();
+
+        // tables hold settings namespaced by an extension, e.g. pg_partman_bgw.dbname
+        if let Some(table) = value.get_ref().as_table() {
+            for (k, v) in table.iter() {
+                if !v.is_str() {
+                    self.error(
+                        value.span(),
+                        format!("postgres configuration `{}.{}` must be a string", name, k),
+                        Some(format!("quote the value, e.g. \"{}\"", v)),
+                    );
+                }
+            }
+            return;
+        }
+
+        let s = match value.get_ref().as_str() {
+            Some(s) => s,
+            None => {
+                self.error(
+                    value.span(),
+                    format!("postgres configuration `{}` must be a string", name),
+                    Some(format!("quote the value, e.g. \"{}\"", value.get_ref())),
+                );
+                return;
+            }
+        };
+
+        // settings namespaced by an extension can't be checked
+        if name.contains('.') {
+            return;
+        }
+
+        let known = KNOWN_POSTGRES_CONFIGS.iter().find(|(n, _)| n == name);
+
+        match known {
+            Some((_, guc_type)) => {
+                if !valid_guc_value(*guc_type, s) {
+                    self.error(
+                        value.span(),
+                        format!(
+                            "invalid value `{}` for postgres configuration `{}`",
+                            s, name
+                        ),
+                        Some(String::from(guc_type_hint(*guc_type))),
+                    );
+                }
+            }
+            None => {
+                let names: Vec<&str> = KNOWN_POSTGRES_CONFIGS.iter().map(|(n, _)| *n).collect();
+
+                self.warning(
+                    key.span(),
+                    format!("unrecognized postgres configuration `{}`", name),
+                    closest(name, &names).map(|c| format!("did you mean `{}`?", c)),
+                );
+            }
+        }
+    }
+
+    fn validate_extension(&mut self, key: &Spanned<String>, extension: &RawExtension) {
+        // a missing enabled or trunk_project is checked on the merged settings, see
+        // validate_merged
+        if let Some(enabled) = &extension.enabled {
+            if !enabled.get_ref().is_bool() {
+                self.error(
+                    enabled.span(),
+                    format!("extension `{}` has an invalid enabled", key.get_ref()),
+                    Some(String::from("use enabled = true or enabled = false")),
+                );
+            }
+        }
+
+        if let Some(version) = &extension.trunk_project_version {
+            if Version::parse(version.get_ref()).is_err() {
+                self.error(
+                    version.span(),
+                    format!("invalid trunk_project_version `{}`", version.get_ref()),
+                    Some(String::from("use a full version such as \"1.2.0\"")),
+                );
+            }
+        }
+
+        // extension versions are whatever the control file names, e.g. "1.5"
+        if let Some(version) = &extension.extension_version {
+            if version.get_ref().trim().is_empty() {
+                self.error(
+                    version.span(),
+                    format!(
+                        "extension `{}` has an empty extension_version",
+                        key.get_ref()
+                    ),
+                    None,
+                );
+            }
+        }
+
+        for location in extension.locations.iter().flatten() {
+            for name in [&location.database, &location.schema].into_iter().flatten() {
+                self.validate_identifier(key, name);
+            }
+        }
+
+        if let Some(project) = &extension.trunk_project {
+            if project.get_ref().trim().is_empty() {
+                self.error(
+                    project.span(),
+                    format!("extension `{}` has an empty trunk_project", key.get_ref()),
+                    None,
+                );
+            }
+        }
+    }
+
+    // database and schema names must fit in a postgres identifier
+    fn validate_identifier(&mut self, key: &Spanned<String>, name: &Spanned<String>) {
+        let length = name.get_ref().len();
+
+        if length == 0 || length > MAX_IDENTIFIER_LENGTH {
+            self.error(
+                name.span(),
+                format!(
+                    "extension `{}` has an invalid location name `{}`",
+                    key.get_ref(),
+                    name.get_ref()
+                ),
+                Some(format!(
+                    "use a name of 1 to {} characters",
+                    MAX_IDENTIFIER_LENGTH
+                )),
+            );
+        }
+    }
+}
+
+fn valid_guc_value(guc_type: GucType, value: &str) -> bool {
+    let value = value.trim();
+
+    match guc_type {
+        GucType::Bool => matches!(
+            value.to_lowercase().as_str(),
+            "on" | "off" | "true" | "false" | "yes" | "no" | "1" | "0"
+        ),
+        GucType::Integer => value.parse::<i64>().is_ok(),
+        GucType::Real => value.parse::<f64>().is_ok(),
+        GucType::Memory => has_number_with_unit(value, &["B", "kB", "MB", "GB", "TB"]),
+        GucType::Time => has_number_with_unit(value, &["us", "ms", "s", "min", "h", "d"]),
+        GucType::String => true,
+    }
+}
+
+// a signed, possibly fractional number followed by an optional unit, e.g. `-1` or `1.5GB`
+fn has_number_with_unit(value: &str, units: &[&str]) -> bool {
+    let split = value
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map(|(i, _)| i)
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let unit = unit.trim();
+
+    number.parse::<f64>().is_ok() && (unit.is_empty() || units.contains(&unit))
+}
+
+fn guc_type_hint(guc_type: GucType) -> &'static str {
+    match guc_type {
+        GucType::Bool => "use \"on\" or \"off\"",
+        GucType::Integer => "use a whole number, e.g. \"100\"",
+        GucType::Real => "use a number, e.g. \"1.1\"",
+        GucType::Memory => "use a number with an optional unit of kB, MB, GB or TB, e.g. \"64MB\"",
+        GucType::Time => {
+            "use a number with an optional unit of us, ms, s, min, h or d, e.g. \"30s\""
+        }
+        GucType::String => "",
+    }
+}
+
+// converts a byte offset into a 1-based line and column
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+    (line, column)
+}
+
+// returns the candidate closest to the given name, if any is close enough to be a likely typo
+fn closest<'b>(name: &str, candidates: &[&'b str]) -> Option<&'b str> {
+    candidates
+        .iter()
+        .map(|c| (levenshtein(name, c), *c))
+        .filter(|(distance, c)| *distance <= c.len() / 3 + 1)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b_chars.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+
+        previous = current;
+    }
+
+    previous[b_chars.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_valid_test() {
+        let contents = r#"
+[app]
+cpu = "1"
+memory = "2Gi"
+storage = "10Gi"
+replicas = 1
+
+[app.postgres_configurations]
+work_mem = "64MB"
+statement_timeout = "60"
+log_min_duration_statement = "-1"
+max_wal_size = "1.5GB"
+pg_partman_bgw = { dbname = "postgres" }
+
+[app.extensions.pgmq]
+enabled = true
+trunk_project = "pgmq"
+trunk_project_version = "0.24.0"
+locations = [{ database = "app", schema = "queues", version = "0.24.0" }]
+"#;
+
+        assert_eq!(validate("tembo.toml", contents), vec![]);
+    }
+
+    #[test]
+    fn validate_invalid_test() {
+        let contents = r#"[app]
+cpu = 1
+memory = "2GB"
+storage = "10Gi"
+replicas = 9
+cpus = "1"
+
+[app.postgres_configurations]
+work_mem = "lots"
+shared_bufers = "1GB"
+
+[app.extensions.pgmq]
+enabled = true
+trunk_project_version = "0.24"
+
+[app.extensions.vector]
+enable = true
+"#;
+
+        let diagnostics = validate("tembo.toml", contents);
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+
+        assert!(has_errors(&diagnostics));
+        assert!(messages.contains(
+            &"tembo.toml:6:1: error: unknown instance field `cpus`\n    help: did you mean `cpu`?"
+                .to_string()
+        ));
+        assert!(messages.contains(
+            &"tembo.toml:2:7: error: invalid cpu `1`\n    help: quote the value, e.g. \"1\""
+                .to_string()
+        ));
+        assert!(messages.contains(
+            &"tembo.toml:3:10: error: invalid memory `2GB`\n    help: did you mean \"2Gi\"?"
+                .to_string()
+        ));
+        assert!(messages
+            .iter()
+            .any(|m| m.starts_with("tembo.toml:5:12: error: invalid replicas")));
+        assert!(messages
+            .iter()
+            .any(|m| m.starts_with("tembo.toml:9:12: error: invalid value `lots`")));
+        assert!(messages.contains(
+            &"tembo.toml:10:1: warning: unrecognized postgres configuration `shared_bufers`\n    help: did you mean `shared_buffers`?"
+                .to_string()
+        ));
+        assert!(messages.iter().any(|m| m
+            .starts_with("tembo.toml:14:25: error: extension `pgmq` sets trunk_project_version")));
+        assert!(messages.contains(
+            &"tembo.toml:16:17: error: extension `vector` is missing enabled\n    help: add enabled = true"
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn validate_locations_test() {
+        let contents = r#"[app.extensions.vector]
+enabled = true
+locations = [{ databse = "app" }, { database = "app", schema = "" }]
+"#;
+
+        let messages: Vec<String> = validate("tembo.toml", contents)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert!(messages.contains(
+            &"tembo.toml:3:16: error: unknown extension location field `databse`\n    help: did you mean `database`?"
+                .to_string()
+        ));
+        assert!(messages.iter().any(|m| m.starts_with(
+            "tembo.toml:3:64: error: extension `vector` has an invalid location name ``"
+        )));
+    }
+
+    #[test]
+    fn validate_defaults_test() {
+        let contents = r#"version = "1.0"
+
+[defaults]
+memory = "2Gi"
+replicas = 0
+
+[defaults.extensions.pgmq]
+enabled = true
+
+[app]
+port = 5433
+
+[app.extensions.pgmq]
+trunk_project = "pgmq"
+"#;
+
+        let diagnostics = validate("tembo.toml", contents);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (5, 12));
+
+        let diagnostics = validate("tembo.toml", "version = 1\n");
+        assert_eq!(diagnostics[0].message, "`version` must be a string");
+    }
+
+    #[test]
+    fn validate_stack_test() {
+        let contents = r#"[app]
+stack = "Data-Warehouse"
+
+[app.extensions.pg_later]
+trunk_project_version = "0.0.14"
+
+[app.extensions.pgmq]
+trunk_project = "pgmq"
+
+[other]
+stack = "Standrd"
+"#;
+
+        let messages: Vec<String> = validate("tembo.toml", contents)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "tembo.toml:7:17: error: extension `pgmq` is missing enabled\n    help: add enabled = true"
+                    .to_string(),
+                "tembo.toml:11:9: error: stack `Standrd` is not defined\n    help: did you mean `Standard`?"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_topology_test() {
+        let contents = r#"[app.databases.app]
+owner = "app_owner"
+schema = ["reporting"]
+
+[app.roles.app_owner]
+login = "yes"
+password_env = ""
+
+[app.roles.reader]
+grants = [
+  { database = "app", privileges = ["connect"] },
+  { database = "app", on = "tables", privileges = ["selct"] },
+  { schema = "reporting", privileges = [] },
+]
+
+[[app.default_privileges]]
+database = "app"
+schema = "reporting"
+on = "views"
+privileges = ["select"]
+"#;
+
+        let messages: Vec<String> = validate("tembo.toml", contents)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        for expected in [
+            "tembo.toml:3:1: error: unknown database field `schema`\n    help: did you mean `schemas`?",
+            "tembo.toml:6:9: error: role `app_owner` has an invalid login\n    help: use login = true or login = false",
+            "tembo.toml:7:16: error: role `app_owner` has an empty password_env\n    help: name the environment variable holding the password",
+            "tembo.toml:12:28: error: a grant of role `reader` on `tables` needs a schema\n    help: add schema = \"public\"",
+            "tembo.toml:12:52: error: a grant of role `reader` has an unknown privilege `selct`\n    help: did you mean `select`?",
+            "tembo.toml:13:3: error: a grant of role `reader` is missing database\n    help: add database = \"postgres\"",
+            "tembo.toml:13:3: error: a grant of role `reader` lists no privileges\n    help: add privileges = [\"select\"]",
+            "tembo.toml:16:1: error: a default privilege is missing role",
+            "tembo.toml:19:6: error: a default privilege has an unknown target `views`\n    help: use one of tables, sequences, functions",
+        ] {
+            assert!(messages.contains(&expected.to_string()), "{}", expected);
+        }
+    }
+
+    #[test]
+    fn validate_syntax_error_test() {
+        let diagnostics = validate("tembo.toml", "[app\ncpu = \"1\"\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+}
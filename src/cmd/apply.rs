@@ -1,159 +1,1288 @@
 use crate::Result;
-use clap::{ArgMatches, Command};
+use anyhow::{bail, Context};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
+use spinners::{Spinner, Spinners};
 use std::{
     collections::HashMap,
     fs::{self},
+    io::{self, IsTerminal},
 };
 
-use crate::cli::{docker::Docker, file_utils::FileUtils, tembo_config::InstanceSettings};
+use crate::cli::{
+    cloud_client::{CloudClient, InstanceSpec},
+    container_runtime::{exec_checked, runtime, ContainerRuntime},
+    context::{get_current_context, get_profile, Environment, TARGET_DOCKER, TARGET_TEMBO_CLOUD},
+    docker::{quote_identifier, quote_literal, Docker},
+    extension,
+    file_utils::FileUtils,
+    migrations::{self, MigrationState, MigrationStatus, Migrator},
+    plan::{Action, CurrentSetting, Plan},
+    replication,
+    resources::ResourceLimits,
+    tembo_config::{
+        base_image, split_libraries, ExtensionLocation, InstanceSettings, TemboConfig,
+        DEFAULT_DATABASE, PRELOAD_SETTING,
+    },
+    templates::{Templates, DOCKERFILE_TEMPLATE_NAME, MIGRATIONS_TEMPLATE_NAME},
+    topology::{self, database_exists},
+    trunk::TrunkRegistry,
+    validation::{has_errors, validate, Severity},
+};
 use tera::Tera;
 
+pub const TEMBO_CONFIG_NAME: &str = "tembo.toml";
 const DOCKERFILE_NAME: &str = "Dockerfile";
 const POSTGRESCONF_NAME: &str = "postgres.conf";
+// where postgres.conf is written in the data directory, which the image includes
+const POSTGRESCONF_PATH: &str = "extra-configs/postgres.conf";
+
+// exit code used by `apply --dry-run` when the plan contains changes, so CI can gate on it
+const PLAN_CHANGES_EXIT_CODE: i32 = 2;
+// extensions apply has enabled on an instance, the only ones it drops once they are removed
+const MANAGED_EXTENSIONS_TABLE: &str = "_tembo_extensions";
 
 // Create init subcommand arguments
 pub fn make_subcommand() -> Command {
-    Command::new("apply").about("Applies changes to the context set using the tembo config file")
+    Command::new("apply")
+        .about("Applies changes to the context set using the tembo config file")
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help("Prints the changes that would be applied without making them, exits with 2 when there are changes"),
+        )
+        .arg(
+            Arg::new("allow-destructive")
+                .long("allow-destructive")
+                .action(ArgAction::SetTrue)
+                .help("Drops extensions that are disabled or removed from the tembo config file, along with any objects depending on them"),
+        )
+        .arg(
+            Arg::new("template-dir")
+                .long("template-dir")
+                .action(ArgAction::Set)
+                .help("A directory with Dockerfile.template and/or migrations.sql.template overriding the bundled templates"),
+        )
+}
+
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let env = get_current_context()?;
+
+    let mut instance_settings: HashMap<String, InstanceSettings> = get_instance_settings()?;
+
+    // the command line template directory takes precedence over the one in tembo.toml
+    if let Some(template_dir) = args.get_one::<String>("template-dir") {
+        for instance_setting in instance_settings.values_mut() {
+            instance_setting.template_dir = Some(template_dir.clone());
+        }
+    }
+
+    if env.target == TARGET_DOCKER {
+        resolve_preload_libraries(&mut instance_settings);
+    }
+
+    if args.get_flag("dry-run") {
+        if env.target != TARGET_DOCKER {
+            bail!("--dry-run is only supported for the docker target");
+        }
+
+        return docker_plan(&instance_settings);
+    }
+
+    match env.target.as_str() {
+        TARGET_DOCKER => docker_apply(&instance_settings, args.get_flag("allow-destructive")),
+        TARGET_TEMBO_CLOUD => tembo_cloud_apply(&env, &instance_settings),
+        target => bail!("Context {} has an unknown target: {}", env.name, target),
+    }
+}
+
+// asks the Trunk registry which libraries the enabled extensions need preloaded, the built-in
+// list covering them when it can't be reached
+pub fn resolve_preload_libraries(instance_settings: &mut HashMap<String, InstanceSettings>) {
+    let registry = TrunkRegistry::from_env();
+
+    for instance_setting in instance_settings.values_mut() {
+        if let Err(e) = instance_setting.resolve_preload_libraries(&registry) {
+            warn!(
+                "- Libraries to preload for extensions are taken from a built-in list: {}",
+                e
+            );
+            return;
+        }
+    }
+}
+
+fn docker_apply(
+    instance_settings: &HashMap<String, InstanceSettings>,
+    allow_destructive: bool,
+) -> Result<()> {
+    let runtime = runtime()?;
+    Docker::installed_and_running(&*runtime)?;
+
+    for instance_setting in instance_settings.values() {
+        docker_apply_instance(&*runtime, instance_setting, allow_destructive)?;
+    }
+
+    Ok(())
+}
+
+// creates or updates each tembo.toml instance on Tembo Cloud and waits for it to be ready
+fn tembo_cloud_apply(
+    env: &Environment,
+    instance_settings: &HashMap<String, InstanceSettings>,
+) -> Result<()> {
+    let org_id = env
+        .org_id
+        .clone()
+        .with_context(|| format!("Context {} is missing an org_id", env.name))?;
+    let profile_name = env
+        .profile
+        .clone()
+        .with_context(|| format!("Context {} is missing a profile", env.name))?;
+    let profile = get_profile(&profile_name)?;
+
+    let client = CloudClient::new(&profile, &org_id);
+
+    for instance_setting in instance_settings.values() {
+        if !instance_setting.databases.is_empty()
+            || !instance_setting.roles.is_empty()
+            || !instance_setting.default_privileges.is_empty()
+        {
+            warn!(
+                "Databases, roles and privileges of {} are only applied to local instances",
+                instance_setting.instance_name
+            );
+        }
+
+        let spec = InstanceSpec::from_settings(instance_setting)?;
+        let instance = client.apply_instance(&spec)?;
+
+        client.wait_until_ready(&instance.instance_id)?;
+    }
+
+    Ok(())
+}
+
+// prints the changes apply would make for each instance, exiting with a distinct code if any
+fn docker_plan(instance_settings: &HashMap<String, InstanceSettings>) -> Result<()> {
+    let runtime = runtime()?;
+    let mut has_changes = false;
+
+    let mut names: Vec<&String> = instance_settings.keys().collect();
+    names.sort();
+
+    for name in names {
+        let plan = get_plan(&*runtime, &instance_settings[name])?;
+        has_changes = has_changes || plan.has_changes();
+
+        println!("{}", plan);
+    }
+
+    if has_changes {
+        std::process::exit(PLAN_CHANGES_EXIT_CODE);
+    }
+
+    Ok(())
 }
 
-pub fn execute(_args: &ArgMatches) -> Result<()> {
-    Docker::installed_and_running()?;
+fn get_plan(runtime: &dyn ContainerRuntime, instance_setting: &InstanceSettings) -> Result<Plan> {
+    let instance_name = &instance_setting.instance_name;
+    let mut plan = Plan::new(instance_name);
+
+    let templates = Templates::load(instance_setting.template_dir.as_deref())?;
+
+    for (_, path, contents) in render_instance_files(instance_setting, &templates)? {
+        plan.diff_file(&path, &contents);
+    }
+
+    // extensions with explicit locations are compared location by location
+    let desired_extensions: Vec<String> = instance_setting
+        .enabled_extensions()
+        .into_iter()
+        .filter(|e| instance_setting.extensions[e].locations.is_empty())
+        .collect();
+
+    let migrations = migrations::load_instance(instance_name)?;
+
+    for (name, port) in replication::members(instance_setting).into_iter().skip(1) {
+        if !Docker::exists(runtime, &name)? {
+            plan.push(
+                Action::Add,
+                format!("replica {}", name),
+                vec![format!("port {}", port)],
+            );
+        }
+    }
+
+    for name in replication::extra_replicas(runtime, instance_setting)? {
+        plan.push(
+            Action::Remove,
+            format!("replica {}", name),
+            vec![String::from("replication slot dropped")],
+        );
+    }
+
+    if !Docker::exists(runtime, instance_name)? {
+        plan.push(
+            Action::Add,
+            format!("container {}", instance_name),
+            vec![format!("port {}", instance_setting.port)],
+        );
+
+        topology::plan_new(&mut plan, instance_setting);
+
+        for name in desired_extensions {
+            plan.push(Action::Add, format!("extension {}", name), vec![]);
+        }
+
+        for (name, location) in instance_setting.extension_locations() {
+            plan.push(Action::Add, location_resource(&name, &location), vec![]);
+        }
+
+        diff_migrations(&mut plan, &migrations::status(&migrations, &[]));
+
+        return Ok(plan);
+    }
+
+    topology::diff(runtime, &mut plan, instance_setting)?;
+
+    let installed_extensions = installed_extensions(runtime, instance_name)?;
+    let removed_extensions = extensions_to_drop(runtime, instance_setting)?;
+
+    // extensions kept as dependencies of desired ones are neither added nor removed
+    let installed_extensions: Vec<String> = installed_extensions
+        .into_iter()
+        .filter(|e| desired_extensions.contains(e) || removed_extensions.contains(e))
+        .collect();
+
+    plan.diff_extensions(&desired_extensions, &installed_extensions);
+    diff_extension_locations(runtime, &mut plan, instance_setting)?;
+
+    for upgrade in extension_upgrades(runtime, instance_setting)? {
+        plan.push(
+            Action::Change,
+            format!("extension {} in {}", upgrade.name, upgrade.database),
+            vec![format!("version {} -> {}", upgrade.from, upgrade.to)],
+        );
+    }
+
+    for (name, value) in instance_setting.postgres_config_pairs()? {
+        if name == PRELOAD_SETTING {
+            continue;
+        }
 
-    let instance_settings: HashMap<String, InstanceSettings> = get_instance_settings()?;
+        let current = current_setting(runtime, instance_name, &name)?;
+        plan.diff_setting(&name, &value, current.as_ref());
+    }
+
+    let libraries = instance_setting.preload_libraries()?;
+    let current_libraries = current_preload_libraries(runtime, instance_name)?;
+    if current_libraries != libraries {
+        plan.push(
+            Action::Change,
+            format!("setting {}", PRELOAD_SETTING),
+            vec![
+                format!("{} -> {}", current_libraries.join(","), libraries.join(",")),
+                String::from("restarts the instance"),
+            ],
+        );
+    }
 
-    let rendered_dockerfile: String = get_rendered_dockerfile(instance_settings.clone())?;
+    diff_migrations(
+        &mut plan,
+        &Migrator::new(runtime, instance_name).status(&migrations)?,
+    );
 
-    FileUtils::create_file(
-        DOCKERFILE_NAME.to_string(),
-        DOCKERFILE_NAME.to_string(),
-        rendered_dockerfile,
-        true,
+    Ok(plan)
+}
+
+// reads a setting from pg_settings, falling back to current_setting for placeholders
+fn current_setting(
+    runtime: &dyn ContainerRuntime,
+    instance_name: &str,
+    name: &str,
+) -> Result<Option<CurrentSetting>> {
+    let row = Docker::psql_query(
+        runtime,
+        instance_name,
+        &format!(
+            "select current_setting(name), setting, coalesce(unit, ''), vartype from pg_settings where name = {}",
+            quote_literal(name)
+        ),
     )?;
+    let fields: Vec<&str> = row.trim().split('|').collect();
 
-    let rendered_migrations: String = get_rendered_migrations_file(instance_settings.clone())?;
+    if let [display, setting, unit, vartype] = fields[..] {
+        return Ok(Some(CurrentSetting {
+            display: display.to_string(),
+            setting: setting.to_string(),
+            unit: unit.to_string(),
+            vartype: vartype.to_string(),
+        }));
+    }
 
-    FileUtils::create_file(
-        "extensions".to_string(),
-        "migrations/1_extensions.sql".to_string(), // TODO: Improve file naming
-        rendered_migrations,
-        true,
+    let current = Docker::psql_query(
+        runtime,
+        instance_name,
+        &format!("select current_setting({}, true)", quote_literal(name)),
     )?;
+    let current = current.trim();
+
+    Ok((!current.is_empty()).then(|| CurrentSetting::custom(current)))
+}
+
+fn current_preload_libraries(
+    runtime: &dyn ContainerRuntime,
+    instance_name: &str,
+) -> Result<Vec<String>> {
+    let current = Docker::psql_query(runtime, instance_name, &format!("show {}", PRELOAD_SETTING))?;
+
+    Ok(split_libraries(current.trim()))
+}
+
+// the data volume keeps the postgres.conf it was created with, so the one rendered for the
+// instance is written into the primary and its replicas once they run. A member is reloaded when
+// the file changed, or restarted when a setting such as shared_preload_libraries needs it
+pub fn sync_postgres_config(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+) -> Result<()> {
+    let postgres_config = get_postgres_config(instance_setting)?;
+
+    for (member, _) in replication::members(instance_setting) {
+        let current = runtime.exec(
+            &member,
+            &[
+                String::from("sh"),
+                String::from("-c"),
+                format!("cat \"$PGDATA/{}\"", POSTGRESCONF_PATH),
+            ],
+        )?;
+        let changed = current.exit_code != 0 || current.stdout != postgres_config;
+
+        if changed {
+            // the file is passed as an argument so its contents need no shell quoting
+            exec_checked(
+                runtime,
+                &member,
+                &[
+                    "sh",
+                    "-c",
+                    "mkdir -p \"$PGDATA/$(dirname \"$1\")\" && printf '%s' \"$2\" > \"$PGDATA/$1\"",
+                    "sh",
+                    POSTGRESCONF_PATH,
+                    &postgres_config,
+                ],
+            )?;
+        }
+
+        // pg_file_settings reads the files as they are now, flagging the settings the running
+        // server can't take without a restart
+        let pending = Docker::psql_query(
+            runtime,
+            &member,
+            "select string_agg(distinct name, ',') from pg_file_settings where error = 'setting could not be applied'",
+        )?;
+        let pending = pending.trim();
+
+        if !pending.is_empty() {
+            let mut sp = Spinner::new(
+                Spinners::Line,
+                format!("Restarting {} to apply {}", member, pending),
+            );
+
+            runtime.stop(&member)?;
+            runtime.start(&member)?;
+            Docker::wait_until_ready(runtime, &member)?;
+
+            sp.stop_with_message(format!("- {} restarted to apply {}", member, pending));
+        } else if changed {
+            Docker::psql_query(runtime, &member, "select pg_reload_conf()")?;
+            info!("- {} reloaded {}", member, POSTGRESCONF_NAME);
+        }
+    }
+
+    Ok(())
+}
+
+fn location_resource(name: &str, location: &ExtensionLocation) -> String {
+    match &location.schema {
+        Some(schema) => format!("extension {} in {}.{}", name, location.database, schema),
+        None => format!("extension {} in {}", name, location.database),
+    }
+}
+
+// compares the extensions' explicit locations with the databases of the instance
+fn diff_extension_locations(
+    runtime: &dyn ContainerRuntime,
+    plan: &mut Plan,
+    instance_setting: &InstanceSettings,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+    let mut missing_databases: Vec<String> = vec![];
+
+    for (name, location) in instance_setting.extension_locations() {
+        let resource = location_resource(&name, &location);
+
+        if missing_databases.contains(&location.database)
+            || !database_exists(runtime, instance_name, &location.database)?
+        {
+            // databases declared in tembo.toml are already in the plan
+            if !missing_databases.contains(&location.database)
+                && !instance_setting.databases.contains_key(&location.database)
+            {
+                plan.push(
+                    Action::Add,
+                    format!("database {}", location.database),
+                    vec![],
+                );
+                missing_databases.push(location.database.clone());
+            }
+
+            plan.push(Action::Add, resource, vec![]);
+            continue;
+        }
+
+        let current_schema = Docker::psql_query_database(
+            runtime,
+            instance_name,
+            &location.database,
+            &format!(
+                "select extnamespace::regnamespace from pg_extension where extname = {}",
+                quote_literal(&name)
+            ),
+        )?;
+        let current_schema = current_schema.trim();
 
-    FileUtils::create_file(
-        POSTGRESCONF_NAME.to_string(),
-        POSTGRESCONF_NAME.to_string(),
-        get_postgres_config(instance_settings),
-        true,
+        match &location.schema {
+            _ if current_schema.is_empty() => plan.push(Action::Add, resource, vec![]),
+            Some(schema) if schema != current_schema => plan.push(
+                Action::Change,
+                resource,
+                vec![format!("schema {} -> {}", current_schema, schema)],
+            ),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// creates the extensions with explicit locations, along with missing databases and schemas
+fn enable_extension_locations(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+
+    for (name, location) in instance_setting.extension_locations() {
+        if !database_exists(runtime, instance_name, &location.database)? {
+            Docker::psql_query(
+                runtime,
+                instance_name,
+                &format!("create database {}", quote_identifier(&location.database)),
+            )?;
+            info!(
+                "- Database {} created on {}",
+                location.database, instance_name
+            );
+        }
+
+        let mut statements = vec![];
+        let mut create = format!("create extension if not exists {}", quote_identifier(&name));
+
+        if let Some(schema) = &location.schema {
+            statements.push(format!(
+                "create schema if not exists {}",
+                quote_identifier(schema)
+            ));
+            create.push_str(&format!(" schema {}", quote_identifier(schema)));
+        }
+        if let Some(version) = &location.version {
+            create.push_str(&format!(" version {}", quote_literal(version)));
+        }
+        create.push_str(" cascade");
+        statements.push(create);
+
+        Docker::psql_query_database(
+            runtime,
+            instance_name,
+            &location.database,
+            &statements.join("; "),
+        )?;
+
+        info!(
+            "- {} enabled on {}",
+            location_resource(&name, &location),
+            instance_name
+        );
+    }
+
+    Ok(())
+}
+
+// an extension created at a different version than tembo.toml pins
+#[derive(Debug, PartialEq)]
+struct ExtensionUpgrade {
+    name: String,
+    database: String,
+    from: String,
+    to: String,
+}
+
+fn extension_upgrades(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+) -> Result<Vec<ExtensionUpgrade>> {
+    let instance_name = &instance_setting.instance_name;
+
+    let mut pinned: Vec<(String, String, String)> = instance_setting
+        .enabled_extensions()
+        .into_iter()
+        .filter(|e| instance_setting.extensions[e].locations.is_empty())
+        .filter_map(|e| {
+            let version = instance_setting.extensions[&e].extension_version.clone()?;
+            Some((e, DEFAULT_DATABASE.to_string(), version))
+        })
+        .collect();
+    pinned.extend(
+        instance_setting
+            .extension_locations()
+            .into_iter()
+            .filter_map(|(name, location)| Some((name, location.database, location.version?))),
+    );
+
+    let mut upgrades = vec![];
+
+    for (name, database, to) in pinned {
+        if !database_exists(runtime, instance_name, &database)? {
+            continue;
+        }
+
+        // extensions not created yet are created at the pinned version
+        match extension::installed_version(runtime, instance_name, &database, &name)? {
+            Some(from) if from != to => upgrades.push(ExtensionUpgrade {
+                name,
+                database,
+                from,
+                to,
+            }),
+            _ => {}
+        }
+    }
+
+    Ok(upgrades)
+}
+
+// installs the pinned Trunk packages on the primary and its replicas, then updates the
+// extensions to the pinned versions
+fn upgrade_extensions(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+    let upgrades = extension_upgrades(runtime, instance_setting)?;
+
+    let mut installed: Vec<&str> = vec![];
+
+    for upgrade in &upgrades {
+        let extension = &instance_setting.extensions[&upgrade.name];
+
+        if let Some(project) = &extension.trunk_project {
+            if !installed.contains(&project.as_str()) {
+                for (member, _) in replication::members(instance_setting) {
+                    extension::trunk_install(
+                        runtime,
+                        &member,
+                        project,
+                        extension.trunk_project_version.as_deref(),
+                        instance_setting.pg_version,
+                    )?;
+                }
+                installed.push(project);
+            }
+        }
+
+        extension::update(
+            runtime,
+            instance_name,
+            &upgrade.database,
+            &upgrade.name,
+            &upgrade.from,
+            &upgrade.to,
+        )?;
+    }
+
+    Ok(())
+}
+
+// pending migrations will be applied, edited ones would make apply fail
+fn diff_migrations(plan: &mut Plan, statuses: &[MigrationStatus]) {
+    for status in statuses {
+        let action = match status.state {
+            MigrationState::Pending => Action::Add,
+            MigrationState::ChecksumMismatch => Action::Change,
+            _ => continue,
+        };
+
+        plan.push(
+            action,
+            format!("migration {}", status.version),
+            vec![format!("{} ({})", status.description, status.state)],
+        );
+    }
+}
+
+// renders the files for a single tembo.toml instance in memory, as (name, path, contents)
+pub fn render_instance_files(
+    instance_setting: &InstanceSettings,
+    templates: &Templates,
+) -> Result<Vec<(String, String, String)>> {
+    let instance_name = &instance_setting.instance_name;
+
+    Ok(vec![
+        (
+            DOCKERFILE_NAME.to_string(),
+            format!("{}/{}", instance_name, DOCKERFILE_NAME),
+            get_rendered_dockerfile(instance_setting, templates)?,
+        ),
+        (
+            POSTGRESCONF_NAME.to_string(),
+            format!("{}/{}", instance_name, POSTGRESCONF_NAME),
+            get_postgres_config(instance_setting)?,
+        ),
+    ])
+}
+
+// renders the files for a single tembo.toml instance into its own directory, then builds & runs it
+fn docker_apply_instance(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+    allow_destructive: bool,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+
+    FileUtils::create_dir(
+        format!("{} migrations directory", instance_name),
+        migrations::dir(instance_name),
+    )?;
+
+    let templates = Templates::load(instance_setting.template_dir.as_deref())?;
+    info!(
+        "Rendering {} from templates: {}",
+        instance_name, templates.version
+    );
+
+    for (name, path, contents) in render_instance_files(instance_setting, &templates)? {
+        FileUtils::create_file(name, path, contents, true)?;
+    }
+
+    Docker::build_run(runtime, instance_setting)?;
+
+    if instance_setting.replicas > 1 {
+        replication::start_replicas(runtime, instance_setting)?;
+    }
+
+    Docker::wait_until_ready(runtime, instance_name)?;
+    Docker::check_pg_version(runtime, instance_name, instance_setting.pg_version)?;
+    sync_postgres_config(runtime, instance_setting)?;
+    replication::remove_extra_replicas(runtime, instance_setting)?;
+    topology::reconcile(runtime, instance_setting)?;
+    run_migrations(runtime, instance_setting, &templates)?;
+    enable_extension_locations(runtime, instance_setting)?;
+    upgrade_extensions(runtime, instance_setting)?;
+    drop_extensions(runtime, instance_setting, allow_destructive)?;
+    record_managed_extensions(runtime, instance_setting)?;
+
+    replication::print_connection_strings(instance_setting);
+
+    Ok(())
+}
+
+// the extensions created in the database, plpgsql aside
+fn installed_extensions(
+    runtime: &dyn ContainerRuntime,
+    instance_name: &str,
+) -> Result<Vec<String>> {
+    Ok(Docker::psql_query(
+        runtime,
+        instance_name,
+        "select extname from pg_extension where extname <> 'plpgsql' order by extname",
+    )?
+    .lines()
+    .map(|l| l.trim().to_string())
+    .filter(|l| !l.is_empty())
+    .collect())
+}
+
+// extensions in the database that are disabled in tembo.toml or were enabled by a previous apply
+// and removed since, keeping the ones enabled extensions depend on (such as those created by
+// CASCADE). Extensions created some other way are left alone
+fn extensions_to_drop(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+) -> Result<Vec<String>> {
+    let enabled = instance_setting.enabled_extensions();
+
+    let mut candidates: Vec<String> = instance_setting
+        .extensions
+        .iter()
+        .filter(|(_, extension)| !extension.enabled)
+        .map(|(name, _)| name.clone())
+        .chain(managed_extensions(
+            runtime,
+            &instance_setting.instance_name,
+        )?)
+        .filter(|name| !enabled.contains(name))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+
+    if candidates.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let quote_all = |names: &[String]| -> String {
+        names
+            .iter()
+            .map(|e| quote_literal(e))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    let query = format!(
+        "with recursive kept(oid) as ( \
+           select oid from pg_extension where extname = 'plpgsql' or extname = any(array[{}]::text[]) \
+           union \
+           select d.refobjid from pg_depend d join kept k on d.objid = k.oid \
+           where d.classid = 'pg_extension'::regclass and d.refclassid = 'pg_extension'::regclass \
+         ) \
+         select extname from pg_extension \
+         where extname = any(array[{}]::text[]) and oid not in (select oid from kept) order by extname",
+        quote_all(&enabled),
+        quote_all(&candidates)
+    );
+
+    Ok(
+        Docker::psql_query(runtime, &instance_setting.instance_name, &query)?
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect(),
+    )
+}
+
+// the extensions recorded by previous applies, none before the first one
+fn managed_extensions(runtime: &dyn ContainerRuntime, instance_name: &str) -> Result<Vec<String>> {
+    let exists = Docker::psql_query(
+        runtime,
+        instance_name,
+        &format!(
+            "select to_regclass({}) is not null",
+            quote_literal(MANAGED_EXTENSIONS_TABLE)
+        ),
+    )?;
+
+    if exists.trim() != "t" {
+        return Ok(vec![]);
+    }
+
+    Ok(Docker::psql_query(
+        runtime,
+        instance_name,
+        &format!(
+            "select name from {} order by name",
+            MANAGED_EXTENSIONS_TABLE
+        ),
+    )?
+    .lines()
+    .map(|l| l.trim().to_string())
+    .filter(|l| !l.is_empty())
+    .collect())
+}
+
+// records the enabled extensions so removing them from tembo.toml later drops them, forgetting
+// the ones no longer installed
+fn record_managed_extensions(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+) -> Result<()> {
+    let mut statements = vec![
+        format!(
+            "create table if not exists {} (name text primary key)",
+            MANAGED_EXTENSIONS_TABLE
+        ),
+        format!(
+            "delete from {} where name not in (select extname from pg_extension)",
+            MANAGED_EXTENSIONS_TABLE
+        ),
+    ];
+
+    let enabled = instance_setting.enabled_extensions();
+    if !enabled.is_empty() {
+        let values: Vec<String> = enabled
+            .iter()
+            .map(|e| format!("({})", quote_literal(e)))
+            .collect();
+
+        statements.push(format!(
+            "insert into {} (name) values {} on conflict do nothing",
+            MANAGED_EXTENSIONS_TABLE,
+            values.join(", ")
+        ));
+    }
+
+    Docker::psql_query(
+        runtime,
+        &instance_setting.instance_name,
+        &statements.join("; "),
+    )?;
+
+    Ok(())
+}
+
+// drops extensions no longer wanted, only with --allow-destructive since CASCADE also drops
+// the objects depending on them
+fn drop_extensions(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+    allow_destructive: bool,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+    let extensions = extensions_to_drop(runtime, instance_setting)?;
+
+    if extensions.is_empty() {
+        return Ok(());
+    }
+
+    if !allow_destructive {
+        warn!(
+            "- Extensions {} are disabled or removed on {} but were not dropped, apply with --allow-destructive to drop them",
+            extensions.join(", "),
+            instance_name
+        );
+        return Ok(());
+    }
+
+    if io::stdin().is_terminal() && !confirm_drop(instance_name, &extensions)? {
+        info!("- Extensions on {} were not dropped", instance_name);
+        return Ok(());
+    }
+
+    let names: Vec<String> = extensions.iter().map(|e| quote_identifier(e)).collect();
+    Docker::psql_query(
+        runtime,
+        instance_name,
+        &format!("drop extension if exists {} cascade", names.join(", ")),
     )?;
 
-    Docker::build_run()?;
+    info!(
+        "- Extensions dropped on {}: {}",
+        instance_name,
+        extensions.join(", ")
+    );
+
+    Ok(())
+}
+
+// NOTE: uses println vs logging intentionally
+fn confirm_drop(instance_name: &str, extensions: &[String]) -> Result<bool> {
+    println!(
+        "Drop extensions {} on {} with CASCADE? Objects depending on them will be dropped too [y/N]",
+        extensions.join(", "),
+        instance_name
+    );
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+// runs the rendered migrations, which are safe to repeat, then pending ones from `migrations/`
+fn run_migrations(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+    templates: &Templates,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+    let migrator = Migrator::new(runtime, instance_name);
+
+    let mut sp = Spinner::new(Spinners::Line, "Running SQL migrations".into());
+
+    migrator.run(&get_rendered_migrations_file(instance_setting, templates)?)?;
+
+    let applied = migrator.up(&migrations::load_instance(instance_name)?, None)?;
 
-    Docker::run_sqlx_migrate()?;
+    sp.stop_with_message(format!(
+        "- SQL migrations completed, {} applied from {}",
+        applied.len(),
+        migrations::dir(instance_name)
+    ));
 
     Ok(())
 }
 
 pub fn get_instance_settings() -> Result<HashMap<String, InstanceSettings>> {
+    parse_instance_settings(&read_tembo_config()?)
+}
+
+// reads tembo.toml from the current directory, logging any diagnostics and failing on errors
+pub fn read_tembo_config() -> Result<String> {
     let mut file_path = FileUtils::get_current_working_dir();
     file_path.push_str("/tembo.toml");
 
-    let contents = match fs::read_to_string(file_path.clone()) {
-        Ok(c) => c,
-        Err(e) => {
-            panic!("Couldn't read context file {}: {}", file_path, e);
+    let contents = fs::read_to_string(&file_path)
+        .with_context(|| format!("Couldn't read config file {}", file_path))?;
+
+    let diagnostics = validate(TEMBO_CONFIG_NAME, &contents);
+
+    for diagnostic in &diagnostics {
+        match diagnostic.severity {
+            Severity::Error => error!("{}", diagnostic),
+            Severity::Warning => warn!("{}", diagnostic),
         }
-    };
+    }
+
+    if has_errors(&diagnostics) {
+        bail!("{} is invalid, see the errors above", TEMBO_CONFIG_NAME);
+    }
+
+    Ok(contents)
+}
+
+// parses the contents of tembo.toml, each table other than `[defaults]` becoming its own instance
+pub fn parse_instance_settings(contents: &str) -> Result<HashMap<String, InstanceSettings>> {
+    let instance_settings = TemboConfig::parse(contents)
+        .with_context(|| format!("Unable to load {}", TEMBO_CONFIG_NAME))?
+        .instance_settings()?;
+
+    let mut ports: HashMap<u16, String> = HashMap::new();
 
-    let instance_settings: HashMap<String, InstanceSettings> = match toml::from_str(&contents) {
-        Ok(d) => d,
-        Err(e) => {
-            panic!("Unable to load data. Error: `{}`", e);
+    // the primary and any replicas of each instance need a port of their own
+    for instance_setting in instance_settings.values() {
+        for (name, port) in replication::members(instance_setting) {
+            if let Some(other) = ports.insert(port, name.clone()) {
+                bail!(
+                    "Instances {} and {} are both configured to use port {}",
+                    other,
+                    name,
+                    port
+                );
+            }
         }
-    };
+    }
 
     Ok(instance_settings)
 }
 
 pub fn get_rendered_dockerfile(
-    instance_settings: HashMap<String, InstanceSettings>,
+    instance_setting: &InstanceSettings,
+    templates: &Templates,
 ) -> Result<String> {
-    let filename = "Dockerfile.template";
-    let filepath =
-        "https://raw.githubusercontent.com/tembo-io/tembo-cli/main/tembo/Dockerfile.template";
+    let rendered_dockerfile = render_template(
+        DOCKERFILE_TEMPLATE_NAME,
+        &templates.dockerfile,
+        instance_setting,
+    )?;
 
-    FileUtils::download_file(filepath, filename)?;
+    Ok(format!(
+        "# Generated by tembo from templates: {}\n{}",
+        templates.version, rendered_dockerfile
+    ))
+}
 
-    let contents = match fs::read_to_string(filename) {
-        Ok(c) => c,
-        Err(e) => {
-            panic!("Couldn't read file {}: {}", filename, e);
-        }
-    };
+pub fn get_rendered_migrations_file(
+    instance_setting: &InstanceSettings,
+    templates: &Templates,
+) -> Result<String> {
+    // the template version is only recorded in the Dockerfile, a header here would change the
+    // tracked migration every time tembo is upgraded
+    render_template(
+        MIGRATIONS_TEMPLATE_NAME,
+        &templates.migrations,
+        instance_setting,
+    )
+}
+
+fn render_template(
+    name: &str,
+    contents: &str,
+    instance_setting: &InstanceSettings,
+) -> Result<String> {
+    let mut tera = Tera::default();
+    tera.add_raw_template(name, contents)
+        .with_context(|| format!("Unable to parse template {}", name))?;
 
-    let mut tera = Tera::new("templates/**/*").unwrap();
-    let _ = tera.add_raw_template("dockerfile", &contents);
     let mut context = tera::Context::new();
-    for (_key, value) in instance_settings.iter() {
-        context.insert("extensions", &value.extensions);
-    }
-    let rendered_dockerfile = tera.render("dockerfile", &context).unwrap();
+    context.insert("extensions", &instance_setting.extensions);
+    context.insert("pg_version", &instance_setting.pg_version);
+    context.insert("base_image", &base_image(instance_setting.pg_version));
 
-    Ok(rendered_dockerfile)
+    tera.render(name, &context)
+        .with_context(|| format!("Unable to render template {}", name))
 }
 
-pub fn get_rendered_migrations_file(
-    instance_settings: HashMap<String, InstanceSettings>,
-) -> Result<String> {
-    let filename = "migrations.sql.template";
-    let filepath =
-        "https://raw.githubusercontent.com/tembo-io/tembo-cli/main/tembo/migrations.sql.template";
-
-    FileUtils::download_file(filepath, filename)?;
+fn get_postgres_config(instance_setting: &InstanceSettings) -> Result<String> {
+    let mut postgres_config = String::from("");
+    let limits = ResourceLimits::from_settings(instance_setting)?;
 
-    let contents = match fs::read_to_string(filename) {
-        Ok(c) => c,
-        Err(e) => {
-            panic!("Couldn't read file {}: {}", filename, e);
+    for (key, value) in instance_setting.postgres_config_pairs()? {
+        if key != PRELOAD_SETTING {
+            postgres_config.push_str(&format!("{} = '{}'\n", key, value));
         }
-    };
+    }
 
-    let mut tera = Tera::new("templates/**/*").unwrap();
-    let _ = tera.add_raw_template("migrations", &contents);
-    let mut context = tera::Context::new();
-    for (_key, value) in instance_settings.iter() {
-        context.insert("extensions", &value.extensions);
+    // user entries come first, the libraries enabled extensions need are added after them. It's
+    // set even when empty so a library is unloaded once nothing needs it
+    let libraries = instance_setting.preload_libraries()?;
+    postgres_config.push_str(&format!(
+        "{} = '{}'\n",
+        PRELOAD_SETTING,
+        libraries.join(",")
+    ));
+
+    // memory settings not set explicitly are sized relative to the instance's memory
+    for (key, value) in limits.default_postgres_configs(instance_setting) {
+        postgres_config.push_str(&format!("{} = '{}'\n", key, value));
     }
-    let rendered_dockerfile = tera.render("migrations", &context).unwrap();
 
-    Ok(rendered_dockerfile)
+    Ok(postgres_config)
 }
 
-fn get_postgres_config(instance_settings: HashMap<String, InstanceSettings>) -> String {
-    let mut postgres_config = String::from("");
-    let qoute_new_line = "\'\n";
-    let equal_to_qoute = " = \'";
-    for (_, instance_setting) in instance_settings.iter() {
-        for (key, value) in instance_setting.postgres_configurations.iter() {
-            if value.is_str() {
-                postgres_config.push_str(key.as_str());
-                postgres_config.push_str(equal_to_qoute);
-                postgres_config.push_str(value.as_str().unwrap());
-                postgres_config.push_str(qoute_new_line);
-            }
-            if value.is_table() {
-                for row in value.as_table().iter() {
-                    for (t, v) in row.iter() {
-                        postgres_config.push_str(key.as_str());
-                        postgres_config.push('.');
-                        postgres_config.push_str(t.as_str());
-                        postgres_config.push_str(equal_to_qoute);
-                        postgres_config.push_str(v.as_str().unwrap());
-                        postgres_config.push_str(qoute_new_line);
-                    }
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::container_runtime::{ExecOutput, MockContainerRuntime};
+
+    const TWO_INSTANCES: &str = r#"
+[app]
+port = 5432
+cpu = "1"
+memory = "2Gi"
+storage = "10Gi"
+replicas = 1
+
+[app.postgres_configurations]
+statement_timeout = "60"
+
+[app.extensions]
+
+[analytics]
+port = 5433
+cpu = "2"
+memory = "4Gi"
+storage = "50Gi"
+replicas = 1
+
+[analytics.postgres_configurations]
+work_mem = "64MB"
+
+[analytics.extensions.pg_later]
+enabled = true
+trunk_project = "pg_later"
+
+[analytics.extensions.pg_cron]
+enabled = false
+
+[analytics.extensions.vector]
+enabled = true
+locations = [{ database = "embeddings", schema = "vectors" }]
+"#;
+
+    #[test]
+    fn parse_instance_settings_test() {
+        let instance_settings = parse_instance_settings(TWO_INSTANCES).unwrap();
+
+        assert_eq!(instance_settings.len(), 2);
+        assert_eq!(instance_settings["app"].instance_name, "app");
+        assert_eq!(instance_settings["app"].port, 5432);
+        assert_eq!(instance_settings["analytics"].instance_name, "analytics");
+        assert_eq!(instance_settings["analytics"].port, 5433);
+        assert_eq!(
+            get_postgres_config(&instance_settings["analytics"]).unwrap(),
+            "work_mem = '64MB'\n\
+             shared_preload_libraries = 'pg_later'\n\
+             shared_buffers = '1024MB'\n\
+             effective_cache_size = '3072MB'\n\
+             maintenance_work_mem = '204MB'\n"
+        );
+
+        // disabled extensions aren't created
+        let templates = Templates::load(None).unwrap();
+        let migrations =
+            get_rendered_migrations_file(&instance_settings["analytics"], &templates).unwrap();
+        assert!(migrations.contains("CREATE EXTENSION IF NOT EXISTS pg_later CASCADE;"));
+        assert!(!migrations.contains("pg_cron"));
+        assert!(!migrations.contains("vector"));
+
+        // the base image and Trunk builds follow pg_version
+        let dockerfile =
+            get_rendered_dockerfile(&instance_settings["analytics"], &templates).unwrap();
+        assert!(dockerfile.contains("FROM quay.io/tembo/tembo-local:15\n"));
+        assert!(dockerfile.contains("RUN trunk install --pg-version 15 pg_later\n"));
+        assert_eq!(
+            instance_settings["analytics"].enabled_extensions(),
+            vec![String::from("pg_later")]
+        );
+        let locations = instance_settings["analytics"].extension_locations();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(
+            location_resource(&locations[0].0, &locations[0].1),
+            "extension vector in embeddings.vectors"
+        );
+
+        // two instances on the same port
+        let conflicting = TWO_INSTANCES.replace("port = 5433", "port = 5432");
+        let result = parse_instance_settings(&conflicting);
+        assert!(result.is_err());
     }
 
-    postgres_config
+    #[test]
+    fn extensions_to_drop_test() {
+        let instance_settings = parse_instance_settings(TWO_INSTANCES).unwrap();
+
+        let mut runtime = MockContainerRuntime::new();
+        runtime.expect_exec().returning(|_, cmd| {
+            let sql = cmd.last().unwrap();
+
+            let stdout = if sql.contains("to_regclass") {
+                "t\n"
+            } else if sql.starts_with("select name") {
+                "pg_later\npgmq\n"
+            } else {
+                // only disabled and previously applied extensions are candidates
+                assert!(sql.contains("extname = any(array['pg_cron', 'pgmq']::text[])"));
+                "pgmq\n"
+            };
+
+            Ok(ExecOutput {
+                exit_code: 0,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            })
+        });
+
+        assert_eq!(
+            extensions_to_drop(&runtime, &instance_settings["analytics"]).unwrap(),
+            vec![String::from("pgmq")]
+        );
+    }
+
+    #[test]
+    fn extension_upgrades_test() {
+        let contents = r#"
+[app.extensions.pgmq]
+enabled = true
+trunk_project = "pgmq"
+extension_version = "1.1.1"
+
+[app.extensions.vector]
+enabled = true
+extension_version = "0.5.0"
+locations = [{ database = "embeddings" }]
+
+[app.extensions.pg_cron]
+enabled = true
+extension_version = "1.6"
+"#;
+        let instance_settings = parse_instance_settings(contents).unwrap();
+
+        let mut runtime = MockContainerRuntime::new();
+        runtime.expect_exec().returning(|_, cmd| {
+            let sql = cmd.last().unwrap();
+
+            // pg_cron isn't created yet, vector already is at its pinned version
+            let stdout = if sql.contains("from pg_database") {
+                "1\n"
+            } else if sql.contains("'pgmq'") {
+                "1.0.0\n"
+            } else if sql.contains("'vector'") {
+                "0.5.0\n"
+            } else {
+                ""
+            };
+
+            Ok(ExecOutput {
+                exit_code: 0,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            })
+        });
+
+        assert_eq!(
+            extension_upgrades(&runtime, &instance_settings["app"]).unwrap(),
+            vec![ExtensionUpgrade {
+                name: String::from("pgmq"),
+                database: String::from("postgres"),
+                from: String::from("1.0.0"),
+                to: String::from("1.1.1"),
+            }]
+        );
+    }
+
+    #[test]
+    fn sync_postgres_config_test() {
+        let instance_settings =
+            parse_instance_settings("[app.postgres_configurations]\nwork_mem = '8MB'\n").unwrap();
+        let postgres_config = get_postgres_config(&instance_settings["app"]).unwrap();
+
+        // a container rebuilt on the kept volume still has the postgres.conf it was created
+        // with, so a changed setting is written and reloaded, one the server can't take without
+        // a restart restarts it, and an unchanged file is left alone
+        for (current, pending, writes, reloads, restarts) in [
+            ("work_mem = '4MB'\n", "", 1, 1, 0),
+            ("work_mem = '4MB'\n", "shared_preload_libraries", 1, 0, 1),
+            (postgres_config.as_str(), "", 0, 0, 0),
+        ] {
+            let current = current.to_string();
+            let pending = pending.to_string();
+            let expected = postgres_config.clone();
+
+            let mut runtime = MockContainerRuntime::new();
+            runtime
+                .expect_exec()
+                .withf(move |_, cmd| cmd.len() == 6 && cmd[5] == expected)
+                .times(writes)
+                .returning(|_, _| {
+                    Ok(ExecOutput {
+                        exit_code: 0,
+                        stdout: String::new(),
+                        stderr: String::new(),
+                    })
+                });
+            runtime
+                .expect_exec()
+                .withf(|_, cmd| cmd.last().unwrap() == "select pg_reload_conf()")
+                .times(reloads)
+                .returning(|_, _| {
+                    Ok(ExecOutput {
+                        exit_code: 0,
+                        stdout: String::new(),
+                        stderr: String::new(),
+                    })
+                });
+            runtime.expect_exec().returning(move |_, cmd| {
+                let last = cmd.last().unwrap();
+                let stdout = if last.starts_with("cat ") {
+                    current.clone()
+                } else if last.contains("pg_file_settings") {
+                    format!("{}\n", pending)
+                } else {
+                    String::new()
+                };
+
+                Ok(ExecOutput {
+                    exit_code: 0,
+                    stdout,
+                    stderr: String::new(),
+                })
+            });
+            runtime.expect_stop().times(restarts).returning(|_| Ok(()));
+            runtime.expect_start().times(restarts).returning(|_| Ok(()));
+
+            sync_postgres_config(&runtime, &instance_settings["app"]).unwrap();
+        }
+    }
 }
@@ -0,0 +1,21 @@
+use crate::Result;
+use clap::ArgMatches;
+use simplelog::*;
+
+pub mod show;
+
+// handles all config command calls
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let res = match args.subcommand() {
+        Some(("show", sub_matches)) => show::execute(sub_matches),
+        _ => unreachable!(),
+    };
+
+    if let Err(err) = res {
+        error!("{err}");
+
+        std::process::exit(101);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! config show command
+
+use crate::cli::tembo_config::TemboConfig;
+use crate::cmd::apply::{parse_instance_settings, read_tembo_config};
+use crate::Result;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use toml::{Table, Value};
+
+// example usage: tembo config show --resolved
+pub fn make_subcommand() -> Command {
+    Command::new("show")
+        .about("Command used to show the tembo config file")
+        .arg(
+            Arg::new("resolved")
+                .long("resolved")
+                .action(ArgAction::SetTrue)
+                .help("Show the effective settings of each instance, with defaults applied"),
+        )
+}
+
+// NOTE: uses println vs logging intentionally
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let contents = read_tembo_config()?;
+
+    if !args.get_flag("resolved") {
+        println!("{}", contents);
+
+        return Ok(());
+    }
+
+    let config = TemboConfig::parse(&contents)?;
+    let instance_settings = parse_instance_settings(&contents)?;
+
+    // a toml table keeps its keys sorted, so output is stable between runs
+    let mut resolved = Table::new();
+
+    for name in config.instances.keys() {
+        resolved.insert(name.clone(), Value::try_from(&instance_settings[name])?);
+    }
+
+    println!("{}", toml::to_string(&resolved)?);
+
+    Ok(())
+}
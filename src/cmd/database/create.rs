@@ -1,6 +1,7 @@
 //!  database create command
 
 use crate::cli::config::Config;
+use crate::cli::container_runtime::runtime;
 use crate::cli::database::Database;
 use crate::cli::instance::Instance;
 use crate::Result;
@@ -56,7 +57,8 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
 }
 
 fn create_database(instance: Instance, name: &str, args: &ArgMatches) -> Result<()> {
-    instance.start();
+    let runtime = runtime()?;
+    instance.start(&*runtime)?;
 
     let mut sp = Spinner::new(Spinners::Dots12, "Creating database".into());
 
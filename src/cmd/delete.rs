@@ -1,13 +1,115 @@
-use crate::{cli::docker::Docker, Result};
-use clap::{ArgMatches, Command};
+use crate::{
+    cli::{
+        container_runtime::{runtime, ContainerRuntime},
+        docker::{volume_name, Docker},
+        replication,
+        tembo_config::InstanceSettings,
+    },
+    cmd::apply::get_instance_settings,
+    Result,
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
 
 // Create init subcommand arguments
 pub fn make_subcommand() -> Command {
-    Command::new("delete").about("Deletes database instance locally & on tembo cloud")
+    Command::new("delete")
+        .about("Deletes database instance locally & on tembo cloud")
+        .long_about(
+            "Deletes database instance locally & on tembo cloud. Local data volumes are kept by \
+             default, the next apply starts on the same data; pass --purge to remove them",
+        )
+        .arg(
+            Arg::new("keep-data")
+                .long("keep-data")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("purge")
+                .help("Keep the data volumes for the next apply, the default"),
+        )
+        .arg(
+            Arg::new("purge")
+                .long("purge")
+                .action(ArgAction::SetTrue)
+                .help("Remove the data volumes too, they are kept for the next apply otherwise"),
+        )
 }
 
-pub fn execute(_args: &ArgMatches) -> Result<()> {
-    Docker::stop_remove("tembo-pg")?;
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let instance_settings = get_instance_settings()?;
+    let runtime = runtime()?;
+
+    for instance_setting in instance_settings.values() {
+        delete_instance(&*runtime, instance_setting, args.get_flag("purge"))?;
+    }
+
+    Ok(())
+}
+
+// removes the instance's containers, and its data volumes when purging
+fn delete_instance(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+    purge: bool,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+
+    // replicas first, they stream from the primary
+    for (name, _) in replication::members(instance_setting).iter().rev() {
+        Docker::stop_remove(runtime, name)?;
+    }
+
+    if instance_setting.replicas > 1 {
+        runtime.remove_network(&replication::network_name(instance_name))?;
+    }
+
+    if purge {
+        Docker::remove_data(runtime, instance_name, None)?;
+    } else {
+        info!(
+            "- Data of {} kept in volume {}, `tembo delete --purge` removes it",
+            instance_name,
+            volume_name(instance_name, instance_setting.pg_version)
+        );
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::container_runtime::MockContainerRuntime;
+    use crate::cli::tembo_config::{TemboConfig, PG_VERSIONS};
+
+    #[test]
+    fn delete_instance_test() {
+        let settings = TemboConfig::parse("[app]\n")
+            .unwrap()
+            .instance_settings()
+            .unwrap();
+
+        // data is kept unless --purge is passed
+        for (args, purge) in [
+            (vec!["delete"], false),
+            (vec!["delete", "--keep-data"], false),
+            (vec!["delete", "--purge"], true),
+        ] {
+            let matches = make_subcommand().try_get_matches_from(args).unwrap();
+            assert_eq!(matches.get_flag("purge"), purge);
+
+            let mut runtime = MockContainerRuntime::new();
+            runtime.expect_container_id().returning(|_| Ok(None));
+            runtime
+                .expect_remove_volume()
+                .withf(|name| name.starts_with("tembo-app-pg"))
+                .times(if purge { PG_VERSIONS.len() } else { 0 })
+                .returning(|_| Ok(()));
+
+            delete_instance(&runtime, &settings["app"], purge).unwrap();
+        }
+
+        assert!(make_subcommand()
+            .try_get_matches_from(["delete", "--keep-data", "--purge"])
+            .is_err());
+    }
+}
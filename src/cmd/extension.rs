@@ -2,8 +2,11 @@ use crate::Result;
 use clap::ArgMatches;
 use simplelog::*;
 
+pub mod info;
 pub mod install;
 pub mod list;
+pub mod search;
+pub mod upgrade;
 
 // handles all extension command calls
 pub fn execute(args: &ArgMatches) -> Result<()> {
@@ -11,6 +14,9 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
     let res = match args.subcommand() {
         Some(("list", sub_matches)) => list::execute(sub_matches),
         Some(("install", sub_matches)) => install::execute(sub_matches),
+        Some(("upgrade", sub_matches)) => upgrade::execute(sub_matches),
+        Some(("search", sub_matches)) => search::execute(sub_matches),
+        Some(("info", sub_matches)) => info::execute(sub_matches),
         _ => unreachable!(),
     };
 
@@ -0,0 +1,74 @@
+//! extension info command
+
+use crate::cli::trunk::{select_version, TrunkRegistry};
+use crate::Result;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+// example usage: tembo extension info pgmq
+pub fn make_subcommand() -> Command {
+    Command::new("info")
+        .about("Command used to show the details of an extension in the Trunk registry")
+        .arg(
+            Arg::new("name")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The name of the Trunk project"),
+        )
+}
+
+// NOTE: uses println vs logging intentionally
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let versions = TrunkRegistry::from_env().versions(name)?;
+    let project = select_version(name, &versions, None)?;
+
+    println!("{} {}", project.name, project.version);
+    if let Some(description) = &project.description {
+        println!("      {}", description);
+    }
+
+    println!(
+        "- Versions: {}",
+        versions
+            .iter()
+            .map(|p| p.version.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    );
+
+    let pg_versions: Vec<String> = project
+        .pg_versions()
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    println!(
+        "- Postgres versions: {}",
+        if pg_versions.is_empty() {
+            String::from("unknown")
+        } else {
+            pg_versions.join(", ")
+        }
+    );
+
+    let dependencies = project.dependencies();
+    println!(
+        "- Dependencies: {}",
+        if dependencies.is_empty() {
+            String::from("none")
+        } else {
+            dependencies.join(", ")
+        }
+    );
+
+    let libraries = project.preload_libraries();
+    if libraries.is_empty() {
+        println!("- shared_preload_libraries: not required");
+    } else {
+        println!(
+            "- shared_preload_libraries: requires {}, changing it restarts Postgres",
+            libraries.join(", ")
+        );
+    }
+
+    Ok(())
+}
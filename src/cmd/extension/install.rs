@@ -1,19 +1,30 @@
 //!  extension install command
 
 use crate::cli::config::Config;
-use crate::cli::instance::{InstalledExtension, Instance};
+use crate::cli::container_runtime::{runtime, ContainerRuntime};
+use crate::cli::docker::{quote_identifier, Docker};
+use crate::cli::extension;
+use crate::cli::file_utils::FileUtils;
+use crate::cli::instance::{EnabledExtension, ExtensionLocation, InstalledExtension, Instance};
 use crate::cli::stacks::TrunkInstall;
+use crate::cli::tembo_config;
+use crate::cli::trunk::{select_version, TrunkProject, TrunkRegistry};
+use crate::cmd::apply::TEMBO_CONFIG_NAME;
 use crate::Result;
-use anyhow::bail;
+use anyhow::{bail, Context};
 use chrono::Utc;
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use simplelog::*;
-use std::io;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
 
-// example usage: tembo extension install -n pgmq -i my_instance
+// example usage: tembo extension install -i my_instance -n pgmq --version 1.1.1 --enable-on postgres
 pub fn make_subcommand() -> Command {
     Command::new("install")
         .about("Command used to install extensions for instances")
+        // --version selects the extension's version here
+        .disable_version_flag(true)
         .arg(
             Arg::new("instance")
                 .short('i')
@@ -22,88 +33,357 @@ pub fn make_subcommand() -> Command {
                 .required(true)
                 .help("The name of the instance to install the extension for"),
         )
+        .arg(
+            Arg::new("name")
+                .short('n')
+                .long("name")
+                .action(ArgAction::Set)
+                .conflicts_with("from-file")
+                .help("The Trunk project to install, prompted for when omitted on a terminal"),
+        )
+        .arg(
+            Arg::new("version")
+                .long("version")
+                .action(ArgAction::Set)
+                .requires("name")
+                .help("The version to install, the latest when omitted"),
+        )
+        .arg(
+            Arg::new("enable-on")
+                .long("enable-on")
+                .action(ArgAction::Set)
+                .value_name("DATABASES")
+                .help("Comma separated databases to create the extension in, e.g. postgres,app"),
+        )
+        .arg(
+            Arg::new("from-file")
+                .long("from-file")
+                .action(ArgAction::Set)
+                .value_name("FILE")
+                .help("A file listing one extension per line, as name or name@version"),
+        )
 }
 
 pub fn execute(args: &ArgMatches) -> Result<()> {
     let config = Config::new(args, &Config::full_path(args));
-    let instance_arg = args.try_get_one::<String>("instance").unwrap();
+    let instance_arg = args.get_one::<String>("instance").unwrap();
 
-    println!("What extension would you like to install? Example: pgmq");
-    let mut name_str = String::from("");
+    let databases: Vec<String> = args
+        .get_one::<String>("enable-on")
+        .map(|d| {
+            d.split(',')
+                .map(|d| d.trim().to_string())
+                .filter(|d| !d.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
 
-    io::stdin()
-        .read_line(&mut name_str)
-        .expect("Failed to read line");
-    let name_str = name_str.trim().to_string().replace('\n', "");
-
-    println!(
-        "trying to install extension '{}' on instance '{}'",
-        &name_str,
-        &instance_arg.unwrap()
-    );
+    let requested = match args.get_one::<String>("from-file") {
+        Some(path) => {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Couldn't read extension list {}", path))?;
+            parse_extension_list(&contents)
+        }
+        None => vec![requested_extension(args)?],
+    };
 
     if config.instances.is_empty() {
         println!("- No instances have been configured");
-    } else {
-        let instance = Instance::find(args, instance_arg.unwrap())?;
+        return Ok(());
+    }
+
+    let instance = Instance::find(args, instance_arg)?;
 
-        install_extension(instance, &name_str, args)?;
+    for (name, version) in requested {
+        println!(
+            "trying to install extension '{}' on instance '{}'",
+            &name, instance_arg
+        );
+
+        install_extension(&instance, &name, version, &databases, args)?;
     }
 
     Ok(())
 }
 
-fn install_extension(instance: Instance, name: &str, args: &ArgMatches) -> Result<()> {
-    println!("What version would you like to install? Example: 2.1.0");
-    let mut version_str = String::from("");
+// the extension given with --name and --version, prompted for when neither is given on a terminal
+fn requested_extension(args: &ArgMatches) -> Result<(String, Option<String>)> {
+    if let Some(name) = args.get_one::<String>("name") {
+        return Ok((name.clone(), args.get_one::<String>("version").cloned()));
+    }
+
+    if !io::stdin().is_terminal() {
+        bail!("--name or --from-file is required when not running in a terminal");
+    }
+
+    let name = prompt("What extension would you like to install? Example: pgmq")?;
+    if name.is_empty() {
+        bail!("No extension name given");
+    }
+    let version = prompt("What version would you like to install? Leave empty for the latest")?;
 
+    Ok((name, (!version.is_empty()).then_some(version)))
+}
+
+// NOTE: uses println vs logging intentionally
+fn prompt(question: &str) -> Result<String> {
+    println!("{}", question);
+
+    let mut answer = String::new();
     io::stdin()
-        .read_line(&mut version_str)
-        .expect("Failed to read line");
-    let version_str = version_str.trim().to_string().replace('\n', "");
-
-    // TODO: decide if this should just prompt the user to start the instance first
-    instance.start();
-
-    for extension in &instance.installed_extensions {
-        // TODO: make sure the version is the same, what to do if it is not?
-        if extension.name.clone().unwrap().to_lowercase() == name.to_lowercase() {
-            warn!(
-                "extension {} is already installed for instance {}, remove first before upgrading version",
-                &name,
-                &instance.name.clone().unwrap()
-            );
-        } else {
-            // try installing extension unless already installed
-            let trunk_install = TrunkInstall {
-                name: Some(name.to_string()),
-                version: Some(version_str.clone().to_string()),
-                created_at: Some(Utc::now()),
-            };
-
-            match instance.install_extension(&trunk_install) {
-                Ok(()) => {
-                    info!("extension {} installed", name);
-                    let _ = persist_config(args, trunk_install);
-
-                    // TODO: provide feedback on enabling the extension once enable action is in place
-                    break;
-                }
-                Err(e) => error!("there was an error: {}", e),
+        .read_line(&mut answer)
+        .with_context(|| "Failed to read answer")?;
+
+    Ok(answer.trim().to_string())
+}
+
+// one extension per line as `name` or `name@version`, blank lines and `#` comments are skipped
+fn parse_extension_list(contents: &str) -> Vec<(String, Option<String>)> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('@') {
+            Some((name, version)) => (name.trim().to_string(), Some(version.trim().to_string())),
+            None => (line.to_string(), None),
+        })
+        .collect()
+}
+
+fn install_extension(
+    instance: &Instance,
+    name: &str,
+    version: Option<String>,
+    databases: &[String],
+    args: &ArgMatches,
+) -> Result<()> {
+    let instance_name = instance.name.clone().unwrap();
+
+    // TODO: make sure the version is the same, what to do if it is not?
+    if instance.installed_extensions.iter().any(|e| {
+        e.name
+            .clone()
+            .unwrap_or_default()
+            .eq_ignore_ascii_case(name)
+    }) {
+        warn!(
+            "extension {} is already installed for instance {}, use `tembo extension upgrade` to change its version",
+            name, instance_name
+        );
+        return Ok(());
+    }
+
+    // an unreachable registry doesn't block installs, trunk reports unknown projects itself and
+    // installs the version asked for, or its latest
+    let pg_version = instance.pg_version();
+    let registry = TrunkRegistry::from_env();
+    let (version, extension_names) = match registry.versions(name) {
+        Ok(versions) => resolve_project(name, version.as_deref(), &versions, pg_version)?,
+        Err(e) => {
+            warn!("Unable to check {} in the Trunk registry: {}", name, e);
+            if version.is_none() {
+                warn!(
+                    "The version of {} trunk installs can't be known, it's not pinned in {}",
+                    name, TEMBO_CONFIG_NAME
+                );
             }
+            (version, vec![])
+        }
+    };
+
+    let runtime = runtime()?;
+    if !Docker::exists(&*runtime, &instance_name)? {
+        bail!(
+            "Instance {} has no container, create it with `tembo instance create` first",
+            instance_name
+        );
+    }
+    instance.start(&*runtime)?;
+    Docker::wait_until_ready(&*runtime, &instance_name)?;
+
+    let trunk_install = TrunkInstall {
+        name: Some(name.to_string()),
+        version: version.clone(),
+        created_at: Some(Utc::now()),
+    };
+
+    install(
+        &*runtime,
+        &instance_name,
+        &trunk_install,
+        pg_version,
+        &extension_names,
+        databases,
+    )?;
+    info!("extension {} installed", name);
+
+    persist_config(args, trunk_install, databases)?;
+    record_in_tembo_config(&instance_name, name, version.as_deref(), databases)?;
+
+    Ok(())
+}
+
+// the version of the project to install, the latest when none is asked for, along with its
+// extensions. The resolved version is installed and recorded, rather than whatever is latest when
+// trunk runs
+fn resolve_project(
+    name: &str,
+    version: Option<&str>,
+    versions: &[TrunkProject],
+    pg_version: u8,
+) -> Result<(Option<String>, Vec<String>)> {
+    let project = select_version(name, versions, version)?;
+    let pg_versions = project.pg_versions();
+
+    // projects published without downloads are built by trunk on install
+    if !pg_versions.is_empty() && !pg_versions.contains(&pg_version) {
+        bail!(
+            "{} {} is not built for Postgres {}, only for {}",
+            name,
+            project.version,
+            pg_version,
+            pg_versions
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+
+    Ok((
+        Some(project.version),
+        project
+            .extensions
+            .into_iter()
+            .map(|e| e.extension_name)
+            .collect(),
+    ))
+}
+
+// runs trunk install once, checks the control files landed and creates the extension in the
+// databases. A project's extensions default to one named like the project
+fn install(
+    runtime: &dyn ContainerRuntime,
+    container: &str,
+    trunk_install: &TrunkInstall,
+    pg_version: u8,
+    extension_names: &[String],
+    databases: &[String],
+) -> Result<()> {
+    let name = trunk_install.name.clone().unwrap();
+    let extension_names = if extension_names.is_empty() {
+        vec![name.clone()]
+    } else {
+        extension_names.to_vec()
+    };
+
+    extension::trunk_install(
+        runtime,
+        container,
+        &name,
+        trunk_install.version.as_deref(),
+        pg_version,
+    )
+    .with_context(|| format!("There was an issue installing the extension {}", name))?;
+
+    for extension_name in &extension_names {
+        if !extension::control_file_exists(runtime, container, extension_name)? {
+            bail!(
+                "trunk install of {} finished but {}.control is missing from {}",
+                name,
+                extension_name,
+                container
+            );
+        }
+    }
+
+    // only the extension named like the project is created when it has others
+    let created: Vec<&String> = match extension_names.iter().find(|e| **e == name) {
+        Some(extension_name) => vec![extension_name],
+        None => extension_names.iter().collect(),
+    };
+
+    for database in databases {
+        for extension_name in &created {
+            Docker::psql_query_database(
+                runtime,
+                container,
+                database,
+                &format!(
+                    "create extension if not exists {} cascade",
+                    quote_identifier(extension_name)
+                ),
+            )?;
+            info!("extension {} enabled on {}", extension_name, database);
         }
     }
 
     Ok(())
 }
 
-fn persist_config(args: &ArgMatches, trunk_install: TrunkInstall) -> Result<()> {
+// adds the extension to tembo.toml so apply keeps installing it
+fn record_in_tembo_config(
+    instance_name: &str,
+    name: &str,
+    version: Option<&str>,
+    databases: &[String],
+) -> Result<()> {
+    let file_path = format!(
+        "{}/{}",
+        FileUtils::get_current_working_dir(),
+        TEMBO_CONFIG_NAME
+    );
+
+    if !Path::new(&file_path).exists() {
+        info!(
+            "No {} found, extension {} not recorded",
+            TEMBO_CONFIG_NAME, name
+        );
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(&file_path)
+        .with_context(|| format!("Couldn't read config file {}", file_path))?;
+
+    match tembo_config::add_extension(&contents, instance_name, name, version, databases) {
+        Ok(updated) => {
+            fs::write(&file_path, updated)
+                .with_context(|| format!("Couldn't write config file {}", file_path))?;
+            info!("extension {} added to {}", name, TEMBO_CONFIG_NAME);
+        }
+        Err(e) => warn!(
+            "extension {} not added to {}: {}",
+            name, TEMBO_CONFIG_NAME, e
+        ),
+    }
+
+    Ok(())
+}
+
+fn persist_config(
+    args: &ArgMatches,
+    trunk_install: TrunkInstall,
+    databases: &[String],
+) -> Result<()> {
     let mut config = Config::new(args, &Config::full_path(args));
     let target_instance = args.try_get_one::<String>("instance");
     let installed_extension = InstalledExtension {
+        name: trunk_install.name.clone(),
+        version: trunk_install.version.clone(),
+        created_at: trunk_install.created_at,
+    };
+    let enabled_extension = EnabledExtension {
         name: trunk_install.name,
-        version: trunk_install.version,
+        version: trunk_install.version.clone(),
         created_at: trunk_install.created_at,
+        locations: databases
+            .iter()
+            .map(|database| ExtensionLocation {
+                database: database.clone(),
+                enabled: String::from("true"),
+                version: trunk_install.version.clone().unwrap_or_default(),
+            })
+            .collect(),
     };
 
     for instance in config.instances.iter_mut() {
@@ -113,6 +393,10 @@ fn persist_config(args: &ArgMatches, trunk_install: TrunkInstall) -> Result<()>
             instance
                 .installed_extensions
                 .push(installed_extension.clone());
+
+            if !databases.is_empty() {
+                instance.enabled_extensions.push(enabled_extension.clone());
+            }
         }
     }
 
@@ -124,3 +408,107 @@ fn persist_config(args: &ArgMatches, trunk_install: TrunkInstall) -> Result<()>
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::container_runtime::{ExecOutput, MockContainerRuntime};
+
+    #[test]
+    fn parse_extension_list_test() {
+        let contents = "# queues\npgmq@1.1.1\n\npg_cron # scheduling\n";
+
+        assert_eq!(
+            parse_extension_list(contents),
+            vec![
+                (String::from("pgmq"), Some(String::from("1.1.1"))),
+                (String::from("pg_cron"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_project_test() {
+        let versions: Vec<TrunkProject> = serde_json::from_str(
+            r#"[
+                {"name": "pgmq", "version": "1.1.1",
+                 "extensions": [{"extension_name": "pgmq", "version": "1.1.1"}],
+                 "downloads": [{"link": "https://example.com/pgmq-15.tar.gz", "pg_version": 15}]},
+                {"name": "pgmq", "version": "0.33.1"}
+            ]"#,
+        )
+        .unwrap();
+
+        // the latest is pinned when no version is asked for
+        assert_eq!(
+            resolve_project("pgmq", None, &versions, 15).unwrap(),
+            (Some(String::from("1.1.1")), vec![String::from("pgmq")])
+        );
+        assert_eq!(
+            resolve_project("pgmq", Some("0.33.1"), &versions, 16)
+                .unwrap()
+                .0,
+            Some(String::from("0.33.1"))
+        );
+        assert!(resolve_project("pgmq", None, &versions, 16).is_err());
+    }
+
+    #[test]
+    fn install_test() {
+        let trunk_install = TrunkInstall {
+            name: Some(String::from("pgmq")),
+            version: Some(String::from("1.1.1")),
+            created_at: None,
+        };
+
+        for control_exists in [true, false] {
+            let mut runtime = MockContainerRuntime::new();
+            runtime
+                .expect_exec()
+                .withf(|_, cmd| cmd[0] == "trunk")
+                .times(1)
+                .returning(|_, cmd| {
+                    assert_eq!(
+                        cmd,
+                        [
+                            "trunk",
+                            "install",
+                            "--pg-version",
+                            "16",
+                            "--version",
+                            "1.1.1",
+                            "pgmq"
+                        ]
+                    );
+                    Ok(exec_output(0))
+                });
+            runtime
+                .expect_exec()
+                .withf(|_, cmd| cmd[0] == "sh")
+                .returning(move |_, _| Ok(exec_output(if control_exists { 0 } else { 1 })));
+            runtime
+                .expect_exec()
+                .withf(|_, cmd| cmd[0] == "psql")
+                .times(if control_exists { 2 } else { 0 })
+                .returning(|_, _| Ok(exec_output(0)));
+
+            let result = install(
+                &runtime,
+                "my-app",
+                &trunk_install,
+                16,
+                &[],
+                &[String::from("postgres"), String::from("queues")],
+            );
+            assert_eq!(result.is_ok(), control_exists);
+        }
+    }
+
+    fn exec_output(exit_code: i64) -> ExecOutput {
+        ExecOutput {
+            exit_code,
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+}
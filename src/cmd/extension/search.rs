@@ -0,0 +1,39 @@
+//! extension search command
+
+use crate::cli::trunk::TrunkRegistry;
+use crate::Result;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+// example usage: tembo extension search queue
+pub fn make_subcommand() -> Command {
+    Command::new("search")
+        .about("Command used to search the Trunk registry for extensions")
+        .arg(
+            Arg::new("term")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("Text to look for in extension names and descriptions"),
+        )
+}
+
+// NOTE: uses println vs logging intentionally
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let term = args.get_one::<String>("term").unwrap();
+    let projects = TrunkRegistry::from_env().search(term)?;
+
+    if projects.is_empty() {
+        println!("- No extensions found for {}", term);
+        return Ok(());
+    }
+
+    for project in projects {
+        println!(
+            "{} {} - {}",
+            project.name,
+            project.version,
+            project.description.unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,254 @@
+//!  extension upgrade command
+
+use crate::cli::config::Config;
+use crate::cli::container_runtime::runtime;
+use crate::cli::extension;
+use crate::cli::file_utils::FileUtils;
+use crate::cli::instance::{InstalledExtension, Instance};
+use crate::cli::tembo_config::{self, DEFAULT_DATABASE};
+use crate::cli::trunk::TrunkRegistry;
+use crate::cmd::apply::TEMBO_CONFIG_NAME;
+use crate::Result;
+use anyhow::{bail, Context};
+use chrono::Utc;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
+use std::fs;
+use std::path::Path;
+
+// example usage: tembo extension upgrade -i my_instance -n pg_cron --to 1.6.2
+pub fn make_subcommand() -> Command {
+    Command::new("upgrade")
+        .about("Command used to upgrade an installed extension to another version")
+        .arg(
+            Arg::new("instance")
+                .short('i')
+                .long("instance")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The name of the instance the extension is installed on"),
+        )
+        .arg(
+            Arg::new("name")
+                .short('n')
+                .long("name")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The name of the extension to upgrade"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The Trunk version to install, e.g. 1.6.2"),
+        )
+        .arg(
+            Arg::new("extension-version")
+                .long("extension-version")
+                .action(ArgAction::Set)
+                .help(
+                    "The version to update the extension to, e.g. 1.6, by default the one the Trunk version creates",
+                ),
+        )
+}
+
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let instance_arg = args.get_one::<String>("instance").unwrap();
+    let name = args.get_one::<String>("name").unwrap();
+    let to = args.get_one::<String>("to").unwrap();
+
+    let instance = Instance::find(args, instance_arg)?;
+    let instance_name = instance.name.clone().unwrap();
+
+    if !instance.installed_extensions.iter().any(|e| {
+        e.name
+            .clone()
+            .unwrap_or_default()
+            .eq_ignore_ascii_case(name)
+    }) {
+        bail!(
+            "Extension {} is not installed on instance {}, install it first",
+            name,
+            instance_name
+        );
+    }
+
+    // Trunk versions and extension versions differ, pg_cron 1.6.2 creates the extension at 1.6
+    let extension_version = match args.get_one::<String>("extension-version") {
+        Some(version) => version.clone(),
+        None => registry_extension_version(name, to)?,
+    };
+
+    let runtime = runtime()?;
+    instance.start(&*runtime)?;
+
+    extension::trunk_install(
+        &*runtime,
+        &instance_name,
+        name,
+        Some(to),
+        instance.pg_version(),
+    )
+    .with_context(|| format!("Unable to install version {} of {}", to, name))?;
+
+    for database in databases(&instance, name) {
+        match extension::installed_version(&*runtime, &instance_name, &database, name)? {
+            Some(from) if from == extension_version => info!(
+                "- Extension {} is already at {} in {}",
+                name, extension_version, database
+            ),
+            Some(from) => extension::update(
+                &*runtime,
+                &instance_name,
+                &database,
+                name,
+                &from,
+                &extension_version,
+            )?,
+            None => info!("- Extension {} is not enabled in {}", name, database),
+        }
+    }
+
+    persist_config(args, &instance_name, name, to, &extension_version)?;
+    record_in_tembo_config(&instance_name, name, to, &extension_version)
+}
+
+// the version the registry says the Trunk version creates the extension at
+fn registry_extension_version(name: &str, to: &str) -> Result<String> {
+    let project = TrunkRegistry::from_env()
+        .check_project(name, Some(to))
+        .with_context(|| {
+            format!(
+                "Unable to look up the extension version of {} {}, pass --extension-version",
+                name, to
+            )
+        })?;
+
+    project.extension_version(name).with_context(|| {
+        format!(
+            "Trunk project {} {} doesn't say which extension version it creates, pass --extension-version",
+            name, to
+        )
+    })
+}
+
+// the databases the extension is enabled in, the default database when none are recorded
+fn databases(instance: &Instance, name: &str) -> Vec<String> {
+    let databases: Vec<String> = instance
+        .enabled_extensions
+        .iter()
+        .filter(|e| {
+            e.name
+                .clone()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case(name)
+        })
+        .flat_map(|e| e.locations.iter())
+        .filter(|l| l.enabled == "true")
+        .map(|l| l.database.clone())
+        .collect();
+
+    if databases.is_empty() {
+        vec![DEFAULT_DATABASE.to_string()]
+    } else {
+        databases
+    }
+}
+
+fn persist_config(
+    args: &ArgMatches,
+    instance_name: &str,
+    name: &str,
+    to: &str,
+    extension_version: &str,
+) -> Result<()> {
+    let mut config = Config::new(args, &Config::full_path(args));
+
+    for instance in config.instances.iter_mut() {
+        if !instance
+            .name
+            .clone()
+            .unwrap()
+            .eq_ignore_ascii_case(instance_name)
+        {
+            continue;
+        }
+
+        for installed in instance.installed_extensions.iter_mut() {
+            if installed
+                .name
+                .clone()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case(name)
+            {
+                *installed = InstalledExtension {
+                    name: installed.name.clone(),
+                    version: Some(to.to_string()),
+                    created_at: Some(Utc::now()),
+                };
+            }
+        }
+
+        for enabled in instance.enabled_extensions.iter_mut() {
+            if enabled
+                .name
+                .clone()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case(name)
+            {
+                enabled.version = Some(extension_version.to_string());
+                for location in enabled.locations.iter_mut() {
+                    location.version = extension_version.to_string();
+                }
+            }
+        }
+    }
+
+    if let Err(e) = config.write(&Config::full_path(args)) {
+        bail!("there was an error writing the config: {e}")
+    }
+
+    Ok(())
+}
+
+// pins the new versions in tembo.toml, so apply doesn't install the previous one again
+fn record_in_tembo_config(
+    instance_name: &str,
+    name: &str,
+    to: &str,
+    extension_version: &str,
+) -> Result<()> {
+    let file_path = format!(
+        "{}/{}",
+        FileUtils::get_current_working_dir(),
+        TEMBO_CONFIG_NAME
+    );
+
+    if !Path::new(&file_path).exists() {
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(&file_path)
+        .with_context(|| format!("Couldn't read config file {}", file_path))?;
+
+    match tembo_config::set_extension_versions(
+        &contents,
+        instance_name,
+        name,
+        to,
+        extension_version,
+    ) {
+        Ok(updated) => {
+            fs::write(&file_path, updated)
+                .with_context(|| format!("Couldn't write config file {}", file_path))?;
+            info!("extension {} set to {} in {}", name, to, TEMBO_CONFIG_NAME);
+        }
+        Err(e) => info!(
+            "extension {} not updated in {}: {}",
+            name, TEMBO_CONFIG_NAME, e
+        ),
+    }
+
+    Ok(())
+}
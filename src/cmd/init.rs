@@ -1,5 +1,19 @@
+//! init command, sets up the tembo home directory and scaffolds a project: a commented
+//! tembo.toml, a starter migration and a .gitignore for the files apply generates
+
+use crate::cli::migrations::{self, new_migration_files};
+use crate::cli::stacks::{define_stacks, Stack, Stacks};
+use crate::cli::tembo_config::{DEFAULT_PG_VERSION, PG_VERSIONS};
+use crate::cmd::apply::TEMBO_CONFIG_NAME;
 use crate::Result;
-use clap::{ArgMatches, Command};
+use anyhow::{anyhow, bail, Context};
+use chrono::Utc;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::str::FromStr;
 
 use crate::cli::{
     context::{
@@ -9,62 +23,442 @@ use crate::cli::{
     file_utils::FileUtils,
 };
 
+const DEFAULT_STACK: &str = "Standard";
+const DEFAULT_INSTANCE_NAME: &str = "my-app";
+const DEFAULT_PORT: u16 = 5432;
+const GITIGNORE_NAME: &str = ".gitignore";
+// files apply and the CLI write next to tembo.toml
+const GENERATED_FILES: &[&str] = &["Dockerfile", "postgres.conf", "tembo.log"];
+
+// the answers init scaffolds the project from
+#[derive(Debug, Clone, PartialEq)]
+struct InitOptions {
+    stack: String,
+    instance_name: String,
+    pg_version: u8,
+    port: u16,
+}
+
 // Create init subcommand arguments
+// example usage: tembo init --stack data-warehouse --instance-name warehouse --pg-version 16 --yes
 pub fn make_subcommand() -> Command {
     Command::new("init")
         .about("Initializes a local environment; creates needed context & config files/directories")
+        .arg(
+            Arg::new("stack")
+                .short('s')
+                .long("stack")
+                .action(ArgAction::Set)
+                .help("The stack the instance starts from, see `tembo stack list`"),
+        )
+        .arg(
+            Arg::new("instance-name")
+                .short('i')
+                .long("instance-name")
+                .action(ArgAction::Set)
+                .help("The name of the instance, the current directory's name by default"),
+        )
+        .arg(
+            Arg::new("pg-version")
+                .long("pg-version")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(u8))
+                .help("The major Postgres version of the instance: 14, 15 or 16"),
+        )
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(u16))
+                .help("The host port the instance listens on"),
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .action(ArgAction::SetTrue)
+                .help("Use the defaults for anything not given instead of asking"),
+        )
 }
 
-pub fn execute(_args: &ArgMatches) -> Result<()> {
-    match FileUtils::create_dir("home directory".to_string(), tembo_home_dir()) {
-        Ok(t) => t,
-        Err(e) => {
-            return Err(e);
-        }
-    }
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    FileUtils::create_dir("home directory".to_string(), tembo_home_dir())?;
 
-    match FileUtils::create_file(
+    FileUtils::create_file(
         "context".to_string(),
         tembo_context_file_path(),
         CONTEXT_DEFAULT_TEXT.to_string(),
         false,
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            return Err(e);
-        }
-    }
+    )?;
 
-    match FileUtils::create_file(
+    FileUtils::create_file(
         "credentials".to_string(),
         tembo_credentials_file_path(),
         CREDENTIALS_DEFAULT_TEXT.to_string(),
         false,
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            return Err(e);
-        }
+    )?;
+
+    if Path::new(TEMBO_CONFIG_NAME).exists() {
+        info!(
+            "Tembo config file exists, remove {} to scaffold a new project",
+            TEMBO_CONFIG_NAME
+        );
+        return Ok(());
     }
 
-    match FileUtils::create_file(
+    let stacks = define_stacks()?;
+    let options = init_options(args, &stacks)?;
+    let stack = find_stack(&stacks, &options.stack)?;
+
+    FileUtils::create_file(
         "config".to_string(),
-        "tembo.toml".to_string(),
-        "".to_string(),
+        TEMBO_CONFIG_NAME.to_string(),
+        tembo_toml(&options, stack),
         false,
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            return Err(e);
-        }
+    )?;
+
+    create_starter_migration(&options.instance_name)?;
+    update_gitignore(GITIGNORE_NAME)?;
+
+    // NOTE: uses println vs logging intentionally
+    println!(
+        "- Instance {} on the {} stack, Postgres {} on port {}",
+        options.instance_name, stack.name, options.pg_version, options.port
+    );
+    println!("- Review {} then run `tembo apply`", TEMBO_CONFIG_NAME);
+
+    Ok(())
+}
+
+// the options given as flags, with the rest asked for on a terminal unless --yes is given
+fn init_options(args: &ArgMatches, stacks: &Stacks) -> Result<InitOptions> {
+    let mut options = InitOptions {
+        stack: args
+            .get_one::<String>("stack")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_STACK.to_string()),
+        instance_name: args
+            .get_one::<String>("instance-name")
+            .cloned()
+            .unwrap_or_else(default_instance_name),
+        pg_version: args
+            .get_one::<u8>("pg-version")
+            .copied()
+            .unwrap_or(DEFAULT_PG_VERSION),
+        port: args.get_one::<u16>("port").copied().unwrap_or(DEFAULT_PORT),
+    };
+
+    let interactive = !args.get_flag("yes") && io::stdin().is_terminal();
+
+    if interactive && args.get_one::<String>("stack").is_none() {
+        let names: Vec<&str> = stacks.stacks.iter().map(|s| s.name.as_str()).collect();
+        options.stack = ask(
+            &format!("Stack ({})", names.join(", ")),
+            &options.stack,
+            |answer| find_stack(stacks, answer).map(|s| s.name.clone()),
+        )?;
+    }
+    if interactive && args.get_one::<String>("instance-name").is_none() {
+        options.instance_name = ask("Instance name", &options.instance_name, |answer| {
+            check_instance_name(answer).map(|_| answer.to_string())
+        })?;
     }
+    if interactive && args.get_one::<u8>("pg-version").is_none() {
+        options.pg_version = ask(
+            "Postgres version",
+            &options.pg_version.to_string(),
+            parse_pg_version,
+        )?;
+    }
+    if interactive && args.get_one::<u16>("port").is_none() {
+        options.port = ask("Port", &options.port.to_string(), |answer| {
+            u16::from_str(answer).map_err(|_| anyhow!("`{}` is not a valid port", answer))
+        })?;
+    }
+
+    find_stack(stacks, &options.stack)?;
+    check_instance_name(&options.instance_name)?;
+    parse_pg_version(&options.pg_version.to_string())?;
+
+    Ok(options)
+}
+
+// asks until the answer parses, an empty answer picks the default
+// NOTE: uses println vs logging intentionally
+fn ask<T>(question: &str, default: &str, parse: impl Fn(&str) -> Result<T>) -> Result<T> {
+    loop {
+        print!("{} [{}]: ", question, default);
+        io::stdout().flush()?;
 
-    match FileUtils::create_dir("migrations directory".to_string(), "migrations".to_string()) {
-        Ok(t) => t,
-        Err(e) => {
-            return Err(e);
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .with_context(|| "Failed to read answer")?;
+
+        let answer = match answer.trim() {
+            "" => default,
+            answer => answer,
+        };
+
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(e) => println!("{}", e),
         }
     }
+}
+
+fn find_stack<'a>(stacks: &'a Stacks, name: &str) -> Result<&'a Stack> {
+    stacks.find(name).ok_or_else(|| {
+        anyhow!(
+            "Stack {} is not defined, available stacks: {}",
+            name,
+            stacks
+                .stacks
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )
+    })
+}
+
+fn parse_pg_version(answer: &str) -> Result<u8> {
+    match u8::from_str(answer) {
+        Ok(version) if PG_VERSIONS.contains(&version) => Ok(version),
+        _ => bail!(
+            "Postgres {} is not supported, use one of {}",
+            answer,
+            PG_VERSIONS
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn check_instance_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "Invalid instance name `{}`, use only letters, numbers, dashes and underscores",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+// the current directory's name, made a valid instance name
+fn default_instance_name() -> String {
+    let name: String = Path::new(&FileUtils::get_current_working_dir())
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    match name.trim_matches('-') {
+        "" => DEFAULT_INSTANCE_NAME.to_string(),
+        name => name.to_string(),
+    }
+}
+
+// tembo.toml for the instance, with every setting documented and the optional ones commented out
+fn tembo_toml(options: &InitOptions, stack: &Stack) -> String {
+    let name = &options.instance_name;
+    let stack_default = |value: &Option<String>, default: &str| {
+        value.clone().unwrap_or_else(|| default.to_string())
+    };
+
+    let mut stack_configs = String::new();
+    for config in &stack.postgres_config {
+        stack_configs.push_str(&format!("# {} = \"{}\"\n", config.name, config.value));
+    }
+    if stack_configs.is_empty() {
+        stack_configs.push_str("# statement_timeout = \"60s\"\n");
+    }
+
+    format!(
+        r#"# tembo.toml describes the Postgres instances of this project, one table per instance.
+# `tembo validate` checks this file and `tembo apply` creates or updates the instances.
+# Settings in a [defaults] table apply to every instance, an instance's own settings win.
+
+[{name}]
+# the stack the instance starts from, see `tembo stack show {stack_name}`
+stack = "{stack}"
+# the major Postgres version: 14, 15 or 16
+pg_version = {pg_version}
+# the host port the instance listens on
+port = {port}
+environment = "dev"
+
+# resources, the stack's are used when not set
+# cpu = "{cpu}"
+# memory = "{memory}"
+# storage = "{storage}"
+
+# instances with more than one replica stream to read replicas, up to 5
+# replicas = 1
+
+# Postgres settings, applied on top of the stack's, which are:
+[{name}.postgres_configurations]
+{stack_configs}
+# extensions installed with Trunk, created in the postgres database unless locations are given
+# [{name}.extensions.pgmq]
+# enabled = true
+# trunk_project = "pgmq"
+# trunk_project_version = "1.1.1"
+# locations = [{{ database = "app", schema = "queues" }}]
+
+# databases and schemas created by apply, along with their owner
+# [{name}.databases.app]
+# owner = "app"
+# schemas = ["queues"]
+
+# roles, their password is read from the environment variable named by password_env
+# [{name}.roles.app]
+# login = true
+# password_env = "APP_PASSWORD"
+# grants = [{{ database = "app", privileges = ["connect"] }}]
+
+# privileges on objects created later in a schema
+# [[{name}.default_privileges]]
+# database = "app"
+# schema = "public"
+# role = "app"
+# on = "tables"
+# privileges = ["select", "insert", "update", "delete"]
+"#,
+        name = name,
+        stack_name = stack.name.to_lowercase(),
+        stack = stack.name,
+        pg_version = options.pg_version,
+        port = options.port,
+        cpu = stack_default(&stack.cpu, "1"),
+        memory = stack_default(&stack.memory, "2Gi"),
+        storage = stack_default(&stack.storage, "10Gi"),
+        stack_configs = stack_configs,
+    )
+}
+
+// a first migration so `tembo apply` has something to run, skipped when the instance has some
+fn create_starter_migration(instance_name: &str) -> Result<()> {
+    let dir = migrations::dir(instance_name);
+
+    if !migrations::load_instance(instance_name)?.is_empty() {
+        return Ok(());
+    }
+
+    FileUtils::create_dir(
+        format!("{} migrations directory", instance_name),
+        dir.clone(),
+    )?;
+
+    let version = Utc::now().format("%Y%m%d%H%M%S").to_string();
+    for (file_name, _) in new_migration_files(&version, "init", false) {
+        FileUtils::create_file(
+            file_name.clone(),
+            format!("{}/{}", dir, file_name),
+            String::from(
+                "-- Applied by `tembo apply`, add the schema of your application here, e.g.\n\
+                 -- create table if not exists notes (id bigserial primary key, body text not null);\n",
+            ),
+            false,
+        )?;
+    }
 
     Ok(())
 }
+
+// adds the generated files missing from .gitignore, creating it when needed
+fn update_gitignore(path: &str) -> Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Couldn't read {}", path)),
+    };
+
+    let missing: Vec<&str> = GENERATED_FILES
+        .iter()
+        .filter(|f| !contents.lines().any(|l| l.trim() == **f))
+        .copied()
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut updated = contents.clone();
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str("# generated by tembo\n");
+    for file in missing {
+        updated.push_str(file);
+        updated.push('\n');
+    }
+
+    fs::write(path, updated).with_context(|| format!("Couldn't write {}", path))?;
+    info!("Tembo {} updated", path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::tembo_config::TemboConfig;
+    use crate::cli::validation::{has_errors, validate};
+
+    #[test]
+    fn tembo_toml_test() {
+        let stacks = define_stacks().unwrap();
+        let options = InitOptions {
+            stack: String::from("data-warehouse"),
+            instance_name: String::from("warehouse"),
+            pg_version: 16,
+            port: 5433,
+        };
+        let contents = tembo_toml(&options, find_stack(&stacks, &options.stack).unwrap());
+
+        assert!(!has_errors(&validate(TEMBO_CONFIG_NAME, &contents)));
+
+        let settings = TemboConfig::parse(&contents)
+            .unwrap()
+            .instance_settings()
+            .unwrap();
+        let warehouse = &settings["warehouse"];
+        assert_eq!(warehouse.stack_type, "Data-Warehouse");
+        assert_eq!(warehouse.pg_version, 16);
+        assert_eq!(warehouse.port, 5433);
+        assert_eq!(warehouse.memory, "8Gi");
+    }
+
+    #[test]
+    fn update_gitignore_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".gitignore");
+        let path = path.to_str().unwrap();
+        fs::write(path, "target\npostgres.conf").unwrap();
+
+        update_gitignore(path).unwrap();
+        update_gitignore(path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "target\npostgres.conf\n# generated by tembo\nDockerfile\ntembo.log\n"
+        );
+    }
+}
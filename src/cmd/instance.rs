@@ -3,6 +3,7 @@ use clap::ArgMatches;
 use simplelog::*;
 
 pub mod create;
+pub mod delete;
 pub mod list;
 pub mod start;
 
@@ -11,6 +12,7 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
     // execute the instance subcommands
     let res = match args.subcommand() {
         Some(("create", sub_matches)) => create::execute(sub_matches),
+        Some(("delete", sub_matches)) => delete::execute(sub_matches),
         Some(("list", sub_matches)) => list::execute(sub_matches),
         Some(("start", sub_matches)) => start::execute(sub_matches),
         _ => unreachable!(),
@@ -1,10 +1,12 @@
 //! instance create command
 
 use crate::cli::config::Config;
-use crate::cli::docker::Docker;
+use crate::cli::container_runtime::runtime;
+use crate::cli::docker::{volume_name, Docker};
 use crate::cli::instance::{EnabledExtension, InstalledExtension, Instance};
 use crate::cli::stacks;
 use crate::cli::stacks::Stacks;
+use crate::cli::tembo_config::PG_VERSIONS;
 use crate::Result;
 use anyhow::bail;
 use chrono::prelude::*;
@@ -41,6 +43,14 @@ pub fn make_subcommand() -> Command {
                 .default_value("5432")
                 .help("The port number you want to use for this instance (default is 5432)"),
         )
+        .arg(
+            Arg::new("pg-version")
+                .long("pg-version")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(u8))
+                .default_value("15")
+                .help("The major Postgres version of the instance: 14, 15 or 16"),
+        )
 }
 
 pub fn execute(args: &ArgMatches) -> Result<()> {
@@ -78,7 +88,7 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
 }
 
 fn check_requirements() -> Result<()> {
-    Docker::installed_and_running()
+    Docker::installed_and_running(&*runtime()?)
 }
 
 fn persist_instance_config(matches: &ArgMatches) -> Result<()> {
@@ -88,6 +98,19 @@ fn persist_instance_config(matches: &ArgMatches) -> Result<()> {
     let r#type = matches.get_one::<String>("type").unwrap();
     let name = matches.get_one::<String>("name").unwrap();
     let port = matches.get_one::<String>("port").unwrap();
+    let pg_version = *matches.get_one::<u8>("pg-version").unwrap();
+
+    if !PG_VERSIONS.contains(&pg_version) {
+        bail!(
+            "Postgres {} is not supported, use one of {}",
+            pg_version,
+            PG_VERSIONS
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
 
     let mut instance = Instance {
         name: Some(name.to_string()),
@@ -95,12 +118,14 @@ fn persist_instance_config(matches: &ArgMatches) -> Result<()> {
         port: Some(port.to_string()),
         created_at: Some(Utc::now()),
         version: None,
+        pg_version: Some(pg_version),
+        volume: Some(volume_name(name, pg_version)),
         installed_extensions: vec![],
         enabled_extensions: vec![],
         databases: vec![],
     };
 
-    let stacks: Stacks = stacks::define_stacks();
+    let stacks: Stacks = stacks::define_stacks()?;
 
     for stack in stacks.stacks {
         if stack.name.to_lowercase() == r#type.to_lowercase() {
@@ -0,0 +1,172 @@
+//! instance delete command
+
+use crate::cli::config::Config;
+use crate::cli::container_runtime::{runtime, ContainerRuntime};
+use crate::cli::docker::Docker;
+use crate::cli::instance::Instance;
+use crate::Result;
+use anyhow::{bail, Context};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
+use std::io::{self, IsTerminal};
+
+// example usage: tembo instance delete -n my_app_db --keep-data
+pub fn make_subcommand() -> Command {
+    Command::new("delete")
+        .about("Command used to delete local instances")
+        .arg(
+            Arg::new("name")
+                .short('n')
+                .long("name")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The name of the instance to delete"),
+        )
+        .arg(
+            Arg::new("keep-data")
+                .long("keep-data")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("purge")
+                .help("Keep the data volume, an instance created with the same name reuses it"),
+        )
+        .arg(
+            Arg::new("purge")
+                .long("purge")
+                .action(ArgAction::SetTrue)
+                .help("Remove the data volume along with the instance"),
+        )
+}
+
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let name = args
+        .get_one::<String>("name")
+        .with_context(|| "Name is missing.")?;
+    let instance = Instance::find(args, name)?;
+    let instance_name = instance.name.clone().unwrap();
+
+    let purge = match purge_flag(args) {
+        Some(purge) => purge,
+        None => confirm_purge(&instance)?,
+    };
+
+    let runtime = runtime()?;
+    delete_container(&*runtime, &instance, purge)?;
+
+    let mut config = Config::new(args, &Config::full_path(args));
+    config.instances.retain(|i| {
+        !i.name
+            .clone()
+            .unwrap_or_default()
+            .eq_ignore_ascii_case(&instance_name)
+    });
+
+    if let Err(e) = config.write(&Config::full_path(args)) {
+        bail!("there was an error writing the config: {e}");
+    }
+    info!("- Instance {} deleted", instance_name);
+
+    Ok(())
+}
+
+// whether --purge or --keep-data decided what happens to the data, none when neither is passed
+fn purge_flag(args: &ArgMatches) -> Option<bool> {
+    if args.get_flag("purge") {
+        Some(true)
+    } else if args.get_flag("keep-data") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// removes the instance's container, and its data volume when purging
+fn delete_container(
+    runtime: &dyn ContainerRuntime,
+    instance: &Instance,
+    purge: bool,
+) -> Result<()> {
+    let instance_name = instance.name.clone().unwrap();
+    Docker::stop_remove(runtime, &instance_name)?;
+
+    if purge {
+        Docker::remove_data(runtime, &instance_name, instance.volume.as_deref())?;
+    } else {
+        info!(
+            "- Data of {} kept in volume {}",
+            instance_name,
+            instance.volume()
+        );
+    }
+
+    Ok(())
+}
+
+// NOTE: uses println vs logging intentionally
+fn confirm_purge(instance: &Instance) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!("--keep-data or --purge is required when not running in a terminal");
+    }
+
+    println!(
+        "Remove the data of {} in volume {} too? This can't be undone [y/N]",
+        instance.name.clone().unwrap(),
+        instance.volume()
+    );
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::container_runtime::MockContainerRuntime;
+    use crate::cli::tembo_config::PG_VERSIONS;
+
+    #[test]
+    fn purge_flag_test() {
+        // neither flag asks before removing the data
+        for (args, purge) in [
+            (vec!["delete", "-n", "app"], None),
+            (vec!["delete", "-n", "app", "--keep-data"], Some(false)),
+            (vec!["delete", "-n", "app", "--purge"], Some(true)),
+        ] {
+            let matches = make_subcommand().try_get_matches_from(args).unwrap();
+            assert_eq!(purge_flag(&matches), purge);
+        }
+
+        assert!(make_subcommand()
+            .try_get_matches_from(["delete", "-n", "app", "--keep-data", "--purge"])
+            .is_err());
+    }
+
+    #[test]
+    fn delete_container_test() {
+        // a legacy instance recorded a volume of its own, purged along with the versioned ones
+        let instance = Instance {
+            name: Some(String::from("app")),
+            r#type: Some(String::from("standard")),
+            port: Some(String::from("5432")),
+            version: None,
+            pg_version: None,
+            volume: Some(String::from("app-data")),
+            created_at: None,
+            installed_extensions: vec![],
+            enabled_extensions: vec![],
+            databases: vec![],
+        };
+
+        for (purge, removed) in [(false, 0), (true, PG_VERSIONS.len() + 1)] {
+            let mut runtime = MockContainerRuntime::new();
+            runtime.expect_container_id().returning(|_| Ok(None));
+            runtime
+                .expect_remove_volume()
+                .times(removed)
+                .returning(|_| Ok(()));
+
+            delete_container(&runtime, &instance, purge).unwrap();
+        }
+    }
+}
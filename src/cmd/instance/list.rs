@@ -20,10 +20,11 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
 
         for instance in &config.instances {
             info!(
-                "    {} - type: {}, port: {}",
+                "    {} - type: {}, port: {}, postgres: {}",
                 instance.name.clone().unwrap(),
                 instance.r#type.clone().unwrap(),
-                instance.port.clone().unwrap()
+                instance.port.clone().unwrap(),
+                instance.pg_version()
             );
         }
 
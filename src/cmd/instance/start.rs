@@ -1,5 +1,6 @@
 // instance start command
 use crate::cli::config::Config;
+use crate::cli::container_runtime::runtime;
 use crate::cli::docker::Docker;
 use crate::Result;
 use anyhow::Context;
@@ -36,7 +37,7 @@ pub fn execute(args: &ArgMatches) -> Result {
                 info!(" config has been found");
                 info!(" starting via Docker");
 
-                Docker::start(name, instance)?;
+                Docker::start(&*runtime()?, name, instance)?;
             }
         }
     }
@@ -0,0 +1,62 @@
+use crate::cli::context::{get_current_context, TARGET_DOCKER};
+use crate::cmd::apply::get_instance_settings;
+use crate::Result;
+use anyhow::bail;
+use clap::{Arg, ArgAction, ArgMatches};
+use simplelog::*;
+
+pub mod down;
+pub mod new;
+pub mod status;
+pub mod up;
+
+// handles all migrate command calls
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let res = match args.subcommand() {
+        Some(("status", sub_matches)) => status::execute(sub_matches),
+        Some(("up", sub_matches)) => up::execute(sub_matches),
+        Some(("down", sub_matches)) => down::execute(sub_matches),
+        Some(("new", sub_matches)) => new::execute(sub_matches),
+        _ => unreachable!(),
+    };
+
+    if let Err(err) = res {
+        error!("{err}");
+
+        std::process::exit(101);
+    }
+
+    Ok(())
+}
+
+// the --instance argument shared by the subcommands that run against instances
+pub fn instance_arg() -> Arg {
+    Arg::new("instance")
+        .short('i')
+        .long("instance")
+        .action(ArgAction::Set)
+        .help("The tembo.toml instance to migrate, all instances when not given")
+}
+
+// the tembo.toml instances named by --instance, or all of them, sorted by name
+pub fn selected_instances(args: &ArgMatches) -> Result<Vec<String>> {
+    let mut names: Vec<String> = get_instance_settings()?.into_keys().collect();
+    names.sort();
+
+    match args.get_one::<String>("instance") {
+        Some(name) if names.contains(name) => Ok(vec![name.clone()]),
+        Some(name) => bail!("Instance {} not found in tembo.toml", name),
+        None => Ok(names),
+    }
+}
+
+// the tembo.toml instances the command runs against on the docker target
+pub fn target_instances(args: &ArgMatches) -> Result<Vec<String>> {
+    let env = get_current_context()?;
+
+    if env.target != TARGET_DOCKER {
+        bail!("tembo migrate is only supported for the docker target");
+    }
+
+    selected_instances(args)
+}
@@ -0,0 +1,43 @@
+//! migrate down command
+
+use crate::cli::container_runtime::runtime;
+use crate::cli::migrations::{self, Migrator};
+use crate::cmd::migrate::{instance_arg, target_instances};
+use crate::Result;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
+
+// example usage: tembo migrate down --steps 2
+pub fn make_subcommand() -> Command {
+    Command::new("down")
+        .about("Command used to revert the last applied migrations on local instances")
+        .arg(instance_arg())
+        .arg(
+            Arg::new("steps")
+                .long("steps")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("The number of migrations to revert"),
+        )
+}
+
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let runtime = runtime()?;
+    let steps = *args.get_one::<usize>("steps").unwrap();
+
+    for instance_name in target_instances(args)? {
+        let migrations = migrations::load_instance(&instance_name)?;
+        let reverted = Migrator::new(&*runtime, &instance_name).down(&migrations, steps)?;
+
+        if reverted.is_empty() {
+            info!("- {} has no applied migrations", instance_name);
+        }
+
+        for version in reverted {
+            info!("- Reverted migration {} on {}", version, instance_name);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+//! migrate new command
+
+use crate::cli::file_utils::FileUtils;
+use crate::cli::migrations::{self, new_migration_files};
+use crate::cmd::migrate::selected_instances;
+use crate::Result;
+use anyhow::bail;
+use chrono::Utc;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+// example usage: tembo migrate new create_users -i my-app --reversible
+pub fn make_subcommand() -> Command {
+    Command::new("new")
+        .about("Command used to create a new migration file")
+        .arg(
+            Arg::new("instance")
+                .short('i')
+                .long("instance")
+                .action(ArgAction::Set)
+                .help(
+                    "The tembo.toml instance the migration is for, required with several instances",
+                ),
+        )
+        .arg(
+            Arg::new("name")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("A short description of the migration, used in its file name"),
+        )
+        .arg(
+            Arg::new("reversible")
+                .short('r')
+                .long("reversible")
+                .action(ArgAction::SetTrue)
+                .help("Create up and down files so the migration can be reverted"),
+        )
+}
+
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let version = Utc::now().format("%Y%m%d%H%M%S").to_string();
+
+    let instance_name = match selected_instances(args)?.as_slice() {
+        [instance_name] => instance_name.clone(),
+        [] => bail!("tembo.toml has no instances to create a migration for"),
+        _ => bail!("tembo.toml has several instances, choose one with --instance"),
+    };
+    let dir = migrations::dir(&instance_name);
+
+    FileUtils::create_dir(
+        format!("{} migrations directory", instance_name),
+        dir.clone(),
+    )?;
+
+    for (file_name, contents) in new_migration_files(&version, name, args.get_flag("reversible")) {
+        FileUtils::create_file(
+            file_name.clone(),
+            format!("{}/{}", dir, file_name),
+            contents,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! migrate status command
+
+use crate::cli::container_runtime::runtime;
+use crate::cli::migrations::{self, Migrator};
+use crate::cmd::migrate::{instance_arg, target_instances};
+use crate::Result;
+use clap::{ArgMatches, Command};
+
+// example usage: tembo migrate status -i my-app
+pub fn make_subcommand() -> Command {
+    Command::new("status")
+        .about("Command used to show which migrations are applied on local instances")
+        .arg(instance_arg())
+}
+
+// NOTE: uses println vs logging intentionally
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let runtime = runtime()?;
+
+    for instance_name in target_instances(args)? {
+        let migrations = migrations::load_instance(&instance_name)?;
+        let statuses = Migrator::new(&*runtime, &instance_name).status(&migrations)?;
+
+        println!("{}", instance_name);
+
+        if statuses.is_empty() {
+            println!(
+                "  No migrations found in {}",
+                migrations::dir(&instance_name)
+            );
+        }
+
+        for status in statuses {
+            println!(
+                "  {:<16} {:<18} {:<28} {}",
+                status.version,
+                status.state,
+                status.applied_at.unwrap_or_default(),
+                status.description
+            );
+        }
+    }
+
+    Ok(())
+}
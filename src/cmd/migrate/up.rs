@@ -0,0 +1,42 @@
+//! migrate up command
+
+use crate::cli::container_runtime::runtime;
+use crate::cli::migrations::{self, Migrator};
+use crate::cmd::migrate::{instance_arg, target_instances};
+use crate::Result;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
+
+// example usage: tembo migrate up --to 20230901120000
+pub fn make_subcommand() -> Command {
+    Command::new("up")
+        .about("Command used to apply pending migrations to local instances")
+        .arg(instance_arg())
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(i64))
+                .help("The last migration version to apply"),
+        )
+}
+
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let runtime = runtime()?;
+    let target = args.get_one::<i64>("to").copied();
+
+    for instance_name in target_instances(args)? {
+        let migrations = migrations::load_instance(&instance_name)?;
+        let applied = Migrator::new(&*runtime, &instance_name).up(&migrations, target)?;
+
+        if applied.is_empty() {
+            info!("- {} is up to date", instance_name);
+        }
+
+        for version in applied {
+            info!("- Applied migration {} to {}", version, instance_name);
+        }
+    }
+
+    Ok(())
+}
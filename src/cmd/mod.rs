@@ -1,9 +1,14 @@
 pub mod apply;
 pub mod auth;
+pub mod config;
 pub mod context;
 pub mod database;
 pub mod delete;
 pub mod extension;
 pub mod init;
 pub mod instance;
+pub mod migrate;
 pub mod schema;
+pub mod stack;
+pub mod upgrade_pg;
+pub mod validate;
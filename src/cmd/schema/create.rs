@@ -1,6 +1,7 @@
 //!  database create command
 
 use crate::cli::config::Config;
+use crate::cli::container_runtime::runtime;
 use crate::cli::instance::Instance;
 use crate::cli::schema::Schema;
 use crate::Result;
@@ -65,7 +66,8 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
 }
 
 fn create_schema(instance: Instance, name: &str, args: &ArgMatches) -> Result<()> {
-    instance.start();
+    let runtime = runtime()?;
+    instance.start(&*runtime)?;
 
     let mut sp = Spinner::new(Spinners::Dots12, "Creating schema".into());
 
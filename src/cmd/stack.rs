@@ -0,0 +1,25 @@
+use crate::Result;
+use clap::ArgMatches;
+use simplelog::*;
+
+pub mod list;
+pub mod show;
+
+// handles all stack command calls
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    // execute the stack subcommands
+    let res = match args.subcommand() {
+        Some(("list", sub_matches)) => list::execute(sub_matches),
+        Some(("show", sub_matches)) => show::execute(sub_matches),
+        _ => unreachable!(),
+    };
+
+    if res.is_err() {
+        error!("{}", res.err().unwrap());
+
+        // TODO: adding logging, log error
+        std::process::exit(101);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,19 @@
+//! stack list command
+
+use crate::cli::stacks;
+use crate::Result;
+use clap::{ArgMatches, Command};
+
+// example usage: tembo stack list
+pub fn make_subcommand() -> Command {
+    Command::new("list").about("Command used to list the stacks instances can be created from")
+}
+
+// NOTE: uses println vs logging intentionally
+pub fn execute(_args: &ArgMatches) -> Result<()> {
+    for stack in stacks::define_stacks()?.stacks {
+        println!("{} {} - {}", stack.name, stack.version, stack.description);
+    }
+
+    Ok(())
+}
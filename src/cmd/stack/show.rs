@@ -0,0 +1,73 @@
+//! stack show command
+
+use crate::cli::stacks;
+use crate::Result;
+use anyhow::Context;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+// example usage: tembo stack show data-warehouse
+pub fn make_subcommand() -> Command {
+    Command::new("show")
+        .about("Command used to show the details of a stack")
+        .arg(
+            Arg::new("name")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The name of the stack"),
+        )
+}
+
+// NOTE: uses println vs logging intentionally
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let name = args.get_one::<String>("name").unwrap();
+    let stacks = stacks::define_stacks()?;
+    let stack = stacks.find(name).with_context(|| {
+        format!(
+            "Stack {} not found, see `tembo stack list` for the available stacks",
+            name
+        )
+    })?;
+
+    println!("{} {}", stack.name, stack.version);
+    println!("      {}", stack.description);
+
+    println!(
+        "- Resources: cpu {}, memory {}, storage {}",
+        stack.cpu.as_deref().unwrap_or("default"),
+        stack.memory.as_deref().unwrap_or("default"),
+        stack.storage.as_deref().unwrap_or("default")
+    );
+    println!("- Trunk installs");
+    for install in &stack.trunk_installs {
+        println!(
+            "      {} {}",
+            install.name.clone().unwrap_or_default(),
+            install.version.clone().unwrap_or_default()
+        );
+    }
+
+    println!("- Extensions");
+    for extension in &stack.extensions {
+        let locations: Vec<String> = extension
+            .locations
+            .iter()
+            .map(|l| format!("{} ({})", l.database, l.version))
+            .collect();
+
+        println!(
+            "      {} - locations: {}",
+            extension.name.clone().unwrap_or_default(),
+            locations.join(", ")
+        );
+    }
+
+    println!("- Postgres configuration");
+    if stack.postgres_config.is_empty() {
+        println!("      none");
+    }
+    for config in &stack.postgres_config {
+        println!("      {} = {}", config.name, config.value);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,557 @@
+//! upgrade-pg command, moves a local instance to another major Postgres version. A container is
+//! built for the new version with its extensions reinstalled by Trunk, and pg_upgrade moves the
+//! data over from the old version's volume. When pg_upgrade fails, a dump taken beforehand is
+//! restored instead. Each major version has its own data volume, so the old container and its
+//! volume are kept until the user removes them
+
+use crate::cli::container_runtime::{exec_checked, runtime, ContainerRuntime, RunOptions};
+use crate::cli::context::{get_current_context, TARGET_DOCKER};
+use crate::cli::docker::{volume_name, Docker, DATA_PATH};
+use crate::cli::file_utils::FileUtils;
+use crate::cli::replication;
+use crate::cli::tembo_config::{set_pg_version, InstanceSettings, PG_VERSIONS};
+use crate::cli::templates::Templates;
+use crate::cmd::apply::{
+    get_instance_settings, read_tembo_config, render_instance_files, resolve_preload_libraries,
+    sync_postgres_config, TEMBO_CONFIG_NAME,
+};
+use crate::Result;
+use anyhow::{bail, Context};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use simplelog::*;
+use spinners::{Spinner, Spinners};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+// where the dump is restored from inside the new container
+const RESTORE_PATH: &str = "/tmp/tembo-upgrade.sql";
+
+// the archive of the old version's binaries, kept in its data directory during the upgrade
+const BINARIES_FILE: &str = "tembo-upgrade-bin.tar";
+
+// where the old data volume is mounted in the container running pg_upgrade
+const OLD_DATA_PATH: &str = "/var/lib/postgresql/old";
+
+const UPGRADE_SCRIPT_PATH: &str = "/tmp/tembo-pg-upgrade.sh";
+
+// unpacks the old binaries and runs pg_upgrade as postgres, which it must not be run as root.
+// Takes the old bindir, where the old and the new volume are mounted and the archive name
+const UPGRADE_SCRIPT: &str = r#"set -e
+if [ "$(id -u)" = "0" ]; then
+    if command -v gosu > /dev/null; then
+        exec gosu postgres sh "$0" "$@"
+    fi
+    exec su postgres -s /bin/sh -c "sh $0 $*"
+fi
+
+old_data="$2${PGDATA#$3}"
+trap 'rm -f "$old_data/$4"' EXIT
+rm -rf /tmp/tembo-old && mkdir -p /tmp/tembo-old
+tar -xf "$old_data/$4" -C /tmp/tembo-old
+cd /tmp
+pg_upgrade -b "/tmp/tembo-old$1" -B "$(pg_config --bindir)" -d "$old_data" -D "$PGDATA"
+"#;
+
+// example usage: tembo upgrade-pg -i my-app --to 16
+pub fn make_subcommand() -> Command {
+    Command::new("upgrade-pg")
+        .about("Upgrades a local instance to another major Postgres version")
+        .arg(
+            Arg::new("instance")
+                .short('i')
+                .long("instance")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The tembo.toml instance to upgrade"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(u8))
+                .help(
+                    "The major Postgres version to upgrade to, pg_version in tembo.toml by default",
+                ),
+        )
+        .arg(
+            Arg::new("remove-old")
+                .long("remove-old")
+                .action(ArgAction::SetTrue)
+                .help("Remove the container kept from a previous upgrade of the instance"),
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .action(ArgAction::SetTrue)
+                .help("Remove the old container without asking"),
+        )
+}
+
+pub fn execute(args: &ArgMatches) -> Result<()> {
+    let env = get_current_context()?;
+    if env.target != TARGET_DOCKER {
+        bail!("tembo upgrade-pg is only supported for the docker target");
+    }
+
+    let instance_name = args.get_one::<String>("instance").unwrap();
+    let mut instance_settings = get_instance_settings()?;
+    resolve_preload_libraries(&mut instance_settings);
+    let instance_setting = instance_settings
+        .get(instance_name)
+        .with_context(|| format!("Instance {} not found in tembo.toml", instance_name))?;
+
+    let runtime = runtime()?;
+    Docker::installed_and_running(&*runtime)?;
+
+    if args.get_flag("remove-old") {
+        return remove_old(&*runtime, instance_name, args.get_flag("yes"));
+    }
+
+    let to = args
+        .get_one::<u8>("to")
+        .copied()
+        .unwrap_or(instance_setting.pg_version);
+    if !PG_VERSIONS.contains(&to) {
+        bail!(
+            "Postgres {} is not supported, use one of {}",
+            to,
+            PG_VERSIONS
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+
+    if !Docker::exists(&*runtime, instance_name)? {
+        bail!(
+            "Instance {} has no container, `tembo apply` creates it with Postgres {}",
+            instance_name,
+            to
+        );
+    }
+    runtime.start(instance_name)?;
+    Docker::wait_until_ready(&*runtime, instance_name)?;
+
+    let from = Docker::data_pg_version(&*runtime, instance_name)?
+        .with_context(|| format!("Unable to read the Postgres version of {}", instance_name))?;
+    if to == from {
+        info!("- Instance {} already runs Postgres {}", instance_name, to);
+        return Ok(());
+    }
+    if to < from {
+        bail!(
+            "Instance {} runs Postgres {}, major versions can't be downgraded to {}",
+            instance_name,
+            from,
+            to
+        );
+    }
+
+    let old_name = old_container_name(instance_name, from);
+    if Docker::exists(&*runtime, &old_name)? {
+        bail!(
+            "{} is left from a previous upgrade, remove it first with `tembo upgrade-pg -i {} --remove-old`",
+            old_name,
+            instance_name
+        );
+    }
+
+    // a copy next to the instance files, in case the restore needs to be redone by hand
+    let dump_path = format!("{}/upgrade-pg{}.sql", instance_name, from);
+    upgrade(&*runtime, instance_setting, from, to, Path::new(&dump_path))?;
+
+    let contents = read_tembo_config()?;
+    fs::write(
+        TEMBO_CONFIG_NAME,
+        set_pg_version(&contents, instance_name, to)?,
+    )
+    .with_context(|| format!("Couldn't write {}", TEMBO_CONFIG_NAME))?;
+
+    // NOTE: uses println vs logging intentionally
+    println!(
+        "- Instance {} upgraded from Postgres {} to {}, run `tembo apply` to restore its replicas",
+        instance_name, from, to
+    );
+    println!(
+        "- The Postgres {} container and its data are kept as {}, remove them with `tembo upgrade-pg -i {} --remove-old`",
+        from, old_name, instance_name
+    );
+
+    Ok(())
+}
+
+// the name the container of the previous major version is kept under
+fn old_container_name(instance_name: &str, pg_version: u8) -> String {
+    format!("{}-pg{}", instance_name, pg_version)
+}
+
+// dumps the instance, sets its container aside and moves its data into a new container with
+// pg_upgrade, restoring the dump instead when pg_upgrade fails. The old container is put back
+// when neither works
+fn upgrade(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+    from: u8,
+    to: u8,
+    dump_path: &Path,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+    let old_name = old_container_name(instance_name, from);
+
+    let mut sp = Spinner::new(
+        Spinners::Line,
+        format!("Dumping {} from Postgres {}", instance_name, from),
+    );
+    let dump = exec_checked(runtime, instance_name, &["pg_dumpall", "-U", "postgres"])?;
+
+    fs::write(dump_path, &dump)
+        .with_context(|| format!("Couldn't write {}", dump_path.display()))?;
+    sp.stop_with_message(format!(
+        "- {} dumped to {}",
+        instance_name,
+        dump_path.display()
+    ));
+
+    let old_bindir = stage_binaries(runtime, instance_name)?;
+
+    // replicas stream from the old primary, apply starts them again from the new one
+    for (member, _) in replication::members(instance_setting).into_iter().skip(1) {
+        if Docker::exists(runtime, &member)? {
+            Docker::stop_remove(runtime, &member)?;
+        }
+    }
+
+    runtime.stop(instance_name)?;
+    runtime.rename(instance_name, &old_name)?;
+
+    let mut upgraded = instance_setting.clone();
+    upgraded.pg_version = to;
+    upgraded.replicas = 1;
+
+    let result = render_files(&upgraded)
+        .and_then(|_| build_new(runtime, &upgraded))
+        .and_then(
+            |_| match pg_upgrade(runtime, &upgraded, from, &old_bindir) {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    warn!(
+                        "pg_upgrade of {} failed, restoring the dump instead: {}",
+                        instance_name, e
+                    );
+
+                    Docker::stop_remove(runtime, instance_name)?;
+                    runtime.remove_volume(&volume_name(instance_name, to))?;
+                    build_new(runtime, &upgraded)?;
+                    restore(runtime, &upgraded, &dump)
+                }
+            },
+        );
+
+    if let Err(e) = result {
+        error!(
+            "Upgrade of {} failed, restoring Postgres {}",
+            instance_name, from
+        );
+
+        if Docker::exists(runtime, instance_name)? {
+            Docker::stop_remove(runtime, instance_name)?;
+        }
+        runtime.remove_volume(&volume_name(instance_name, to))?;
+        runtime.rename(&old_name, instance_name)?;
+        runtime.start(instance_name)?;
+        exec_checked(
+            runtime,
+            instance_name,
+            &["sh", "-c", "rm -f \"$PGDATA/$1\"", "sh", BINARIES_FILE],
+        )?;
+
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+// archives the old version's binaries, libraries and extension files into its data directory,
+// where pg_upgrade in the new container finds them, returning the old bindir
+fn stage_binaries(runtime: &dyn ContainerRuntime, instance_name: &str) -> Result<String> {
+    let bindir = exec_checked(
+        runtime,
+        instance_name,
+        &[
+            "sh",
+            "-c",
+            "tar -cf \"$PGDATA/$1\" \"$(pg_config --bindir)\" \"$(pg_config --pkglibdir)\" \"$(pg_config --sharedir)\" 2> /dev/null \
+             || { rm -f \"$PGDATA/$1\"; exit 1; }; pg_config --bindir",
+            "sh",
+            BINARIES_FILE,
+        ],
+    )
+    .with_context(|| format!("Unable to archive the Postgres binaries of {}", instance_name))?;
+
+    Ok(bindir.trim().to_string())
+}
+
+// renders the instance files for the new version, its Dockerfile installing the extensions
+fn render_files(instance_setting: &InstanceSettings) -> Result<()> {
+    let templates = Templates::load(instance_setting.template_dir.as_deref())?;
+    for (name, path, contents) in render_instance_files(instance_setting, &templates)? {
+        FileUtils::create_file(name, path, contents, true)?;
+    }
+
+    Ok(())
+}
+
+// builds and starts the container for the new version, initializing an empty data volume
+fn build_new(runtime: &dyn ContainerRuntime, instance_setting: &InstanceSettings) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+
+    Docker::build_run(runtime, instance_setting)?;
+    Docker::wait_until_ready(runtime, instance_name)?;
+    Docker::check_pg_version(runtime, instance_name, instance_setting.pg_version)?;
+    sync_postgres_config(runtime, instance_setting)?;
+
+    Ok(())
+}
+
+// runs pg_upgrade from a container of the new image that mounts both data volumes, then starts
+// the new container on the upgraded data
+fn pg_upgrade(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+    from: u8,
+    old_bindir: &str,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+    let helper_name = format!("{}-pg-upgrade", instance_name);
+
+    let mut sp = Spinner::new(
+        Spinners::Line,
+        format!(
+            "Upgrading {} to Postgres {} with pg_upgrade",
+            instance_name, instance_setting.pg_version
+        ),
+    );
+
+    runtime.stop(instance_name)?;
+    runtime.run(&RunOptions {
+        name: helper_name.clone(),
+        image: format!("postgres-{}", instance_name),
+        volumes: vec![
+            (volume_name(instance_name, from), OLD_DATA_PATH.to_string()),
+            (
+                volume_name(instance_name, instance_setting.pg_version),
+                DATA_PATH.to_string(),
+            ),
+        ],
+        entrypoint: Some(vec![
+            String::from("tail"),
+            String::from("-f"),
+            String::from("/dev/null"),
+        ]),
+        ..RunOptions::default()
+    })?;
+
+    let result = Docker::write_file(runtime, &helper_name, UPGRADE_SCRIPT_PATH, UPGRADE_SCRIPT)
+        .and_then(|_| {
+            exec_checked(
+                runtime,
+                &helper_name,
+                &[
+                    "sh",
+                    UPGRADE_SCRIPT_PATH,
+                    old_bindir,
+                    OLD_DATA_PATH,
+                    DATA_PATH,
+                    BINARIES_FILE,
+                ],
+            )
+            .map_err(|e| e.into())
+        });
+    sp.stop_with_newline();
+    Docker::stop_remove(runtime, &helper_name)?;
+    result?;
+
+    runtime.start(instance_name)?;
+    Docker::wait_until_ready(runtime, instance_name)?;
+    // pg_upgrade doesn't carry over planner statistics
+    exec_checked(
+        runtime,
+        instance_name,
+        &["vacuumdb", "-U", "postgres", "--all", "--analyze-in-stages"],
+    )?;
+    info!("- {} upgraded with pg_upgrade", instance_name);
+
+    Ok(())
+}
+
+// loads the dump into the new container, failing on the first error so a partial restore is
+// never taken for an upgrade
+fn restore(
+    runtime: &dyn ContainerRuntime,
+    instance_setting: &InstanceSettings,
+    dump: &str,
+) -> Result<()> {
+    let instance_name = &instance_setting.instance_name;
+
+    let mut sp = Spinner::new(
+        Spinners::Line,
+        format!(
+            "Restoring {} into Postgres {}",
+            instance_name, instance_setting.pg_version
+        ),
+    );
+
+    Docker::write_file(runtime, instance_name, RESTORE_PATH, &restore_script(dump))?;
+
+    let output = runtime.exec(
+        instance_name,
+        &[
+            String::from("psql"),
+            String::from("-U"),
+            String::from("postgres"),
+            String::from("-q"),
+            String::from("-v"),
+            String::from("ON_ERROR_STOP=1"),
+            String::from("-f"),
+            String::from(RESTORE_PATH),
+        ],
+    )?;
+    exec_checked(runtime, instance_name, &["rm", "-f", RESTORE_PATH])?;
+
+    if output.exit_code != 0 {
+        sp.stop_with_newline();
+        bail!("Restoring the dump failed: {}", output.stderr.trim());
+    }
+    sp.stop_with_message(format!("- {} restored", instance_name));
+
+    Ok(())
+}
+
+// the dump without creating the postgres role, which initdb already made in the new cluster and
+// would otherwise stop the restore
+fn restore_script(dump: &str) -> String {
+    dump.lines()
+        .filter(|l| *l != "CREATE ROLE postgres;")
+        .map(|l| format!("{}\n", l))
+        .collect()
+}
+
+// removes the container a previous upgrade kept, along with its data volume
+fn remove_old(runtime: &dyn ContainerRuntime, instance_name: &str, yes: bool) -> Result<()> {
+    let mut found = false;
+
+    for pg_version in PG_VERSIONS {
+        let old_name = old_container_name(instance_name, *pg_version);
+
+        if !Docker::exists(runtime, &old_name)? {
+            continue;
+        }
+        found = true;
+
+        if !yes && !confirm_remove(&old_name)? {
+            info!("- {} kept", old_name);
+            continue;
+        }
+
+        Docker::stop_remove(runtime, &old_name)?;
+        runtime.remove_volume(&volume_name(instance_name, *pg_version))?;
+        info!("- {} and its data removed", old_name);
+    }
+
+    if !found {
+        info!("- No container kept from an upgrade of {}", instance_name);
+    }
+
+    Ok(())
+}
+
+// NOTE: uses println vs logging intentionally
+fn confirm_remove(old_name: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!(
+            "Pass --yes to remove {} when not running in a terminal",
+            old_name
+        );
+    }
+
+    println!(
+        "Remove {} and the data it holds? This can't be undone [y/N]",
+        old_name
+    );
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::container_runtime::{ExecOutput, MockContainerRuntime};
+    use crate::cli::tembo_config::TemboConfig;
+
+    #[test]
+    fn upgrade_rollback_test() {
+        // the templates can't be loaded, so the new container fails before any file is rendered
+        let settings = TemboConfig::parse(
+            "[upgrade-rollback-test]\npg_version = 16\ntemplate_dir = \"/does/not/exist\"\n",
+        )
+        .unwrap()
+        .instance_settings()
+        .unwrap();
+        let instance_setting = &settings["upgrade-rollback-test"];
+        let dir = tempfile::tempdir().unwrap();
+        let dump_path = dir.path().join("upgrade-pg15.sql");
+
+        let mut runtime = MockContainerRuntime::new();
+        runtime.expect_exec().returning(|_, _| {
+            Ok(ExecOutput {
+                exit_code: 0,
+                stdout: String::from("-- dump\n"),
+                stderr: String::new(),
+            })
+        });
+        runtime.expect_stop().returning(|_| Ok(()));
+        runtime.expect_container_id().returning(|_| Ok(None));
+        runtime
+            .expect_rename()
+            .withf(|name, new_name| {
+                name == "upgrade-rollback-test" && new_name == "upgrade-rollback-test-pg15"
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        // the new container can't be set up, so the old one is put back
+        runtime
+            .expect_rename()
+            .withf(|name, new_name| {
+                name == "upgrade-rollback-test-pg15" && new_name == "upgrade-rollback-test"
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        runtime
+            .expect_remove_volume()
+            .withf(|name| name == "tembo-upgrade-rollback-test-pg16")
+            .times(1)
+            .returning(|_| Ok(()));
+        runtime.expect_start().times(1).returning(|_| Ok(()));
+
+        let result = upgrade(&runtime, instance_setting, 15, 16, &dump_path);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&dump_path).unwrap(), "-- dump\n");
+    }
+
+    #[test]
+    fn restore_script_test() {
+        let dump = "CREATE ROLE app;\nCREATE ROLE postgres;\nALTER ROLE postgres WITH SUPERUSER;\n";
+
+        assert_eq!(
+            restore_script(dump),
+            "CREATE ROLE app;\nALTER ROLE postgres WITH SUPERUSER;\n"
+        );
+    }
+}
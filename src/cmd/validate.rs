@@ -0,0 +1,37 @@
+use crate::cli::validation::{has_errors, validate, Severity};
+use crate::cmd::apply::TEMBO_CONFIG_NAME;
+use crate::Result;
+use anyhow::{bail, Context};
+use clap::{ArgMatches, Command};
+use std::fs;
+
+// Create validate subcommand arguments
+pub fn make_subcommand() -> Command {
+    Command::new("validate")
+        .about("Validates the tembo config file, reporting the location of any problems")
+}
+
+// NOTE: uses println vs logging intentionally
+pub fn execute(_args: &ArgMatches) -> Result<()> {
+    let contents = fs::read_to_string(TEMBO_CONFIG_NAME)
+        .with_context(|| format!("Couldn't read config file {}", TEMBO_CONFIG_NAME))?;
+
+    let diagnostics = validate(TEMBO_CONFIG_NAME, &contents);
+
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    if has_errors(&diagnostics) {
+        let count = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+
+        bail!("{} has {} error(s)", TEMBO_CONFIG_NAME, count);
+    }
+
+    println!("- {} is valid", TEMBO_CONFIG_NAME);
+
+    Ok(())
+}
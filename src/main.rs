@@ -51,12 +51,17 @@ fn main() {
     let res = match matches.subcommand() {
         Some(("init", sub_matches)) => cmd::init::execute(sub_matches),
         Some(("context", sub_matches)) => cmd::context::execute(sub_matches),
+        Some(("config", sub_matches)) => cmd::config::execute(sub_matches),
         Some(("apply", sub_matches)) => cmd::apply::execute(sub_matches),
+        Some(("validate", sub_matches)) => cmd::validate::execute(sub_matches),
         Some(("delete", sub_matches)) => cmd::delete::execute(sub_matches),
+        Some(("migrate", sub_matches)) => cmd::migrate::execute(sub_matches),
         Some(("instance", sub_matches)) => cmd::instance::execute(sub_matches),
         Some(("db", sub_matches)) => cmd::database::execute(sub_matches),
         Some(("schema", sub_matches)) => cmd::schema::execute(sub_matches),
         Some(("extension", sub_matches)) => cmd::extension::execute(sub_matches),
+        Some(("stack", sub_matches)) => cmd::stack::execute(sub_matches),
+        Some(("upgrade-pg", sub_matches)) => cmd::upgrade_pg::execute(sub_matches),
         Some(("auth", sub_matches)) => cmd::auth::execute(sub_matches),
         Some(("completions", sub_matches)) => (|| {
             let shell = sub_matches
@@ -96,13 +101,24 @@ fn create_clap_command() -> Command {
         )
         .subcommand(cmd::init::make_subcommand())
         .subcommand(cmd::apply::make_subcommand())
+        .subcommand(cmd::validate::make_subcommand())
         .subcommand(cmd::delete::make_subcommand())
+        .subcommand(cmd::upgrade_pg::make_subcommand())
+        .subcommand(
+            Command::new("migrate")
+                .about("Commands used to manage migrations of local instances")
+                .subcommand(cmd::migrate::status::make_subcommand())
+                .subcommand(cmd::migrate::up::make_subcommand())
+                .subcommand(cmd::migrate::down::make_subcommand())
+                .subcommand(cmd::migrate::new::make_subcommand()),
+        )
         .subcommand(
             Command::new("instance")
                 .about("Commands used to manage local and cloud instances")
                 .subcommand(cmd::instance::create::make_subcommand())
                 .subcommand(cmd::instance::list::make_subcommand())
-                .subcommand(cmd::instance::start::make_subcommand()),
+                .subcommand(cmd::instance::start::make_subcommand())
+                .subcommand(cmd::instance::delete::make_subcommand()),
         )
         .subcommand(
             Command::new("context")
@@ -110,6 +126,11 @@ fn create_clap_command() -> Command {
                 .subcommand(cmd::context::list::make_subcommand())
                 .subcommand(cmd::context::set::make_subcommand()),
         )
+        .subcommand(
+            Command::new("config")
+                .about("Commands used to show the tembo config file")
+                .subcommand(cmd::config::show::make_subcommand()),
+        )
         .subcommand(
             Command::new("auth")
                 .about("Commands used to manage authentication")
@@ -130,7 +151,16 @@ fn create_clap_command() -> Command {
             Command::new("extension")
                 .about("Commands used to manage local and cloud extensions")
                 .subcommand(cmd::extension::list::make_subcommand())
-                .subcommand(cmd::extension::install::make_subcommand()),
+                .subcommand(cmd::extension::install::make_subcommand())
+                .subcommand(cmd::extension::upgrade::make_subcommand())
+                .subcommand(cmd::extension::search::make_subcommand())
+                .subcommand(cmd::extension::info::make_subcommand()),
+        )
+        .subcommand(
+            Command::new("stack")
+                .about("Commands used to list and show stacks")
+                .subcommand(cmd::stack::list::make_subcommand())
+                .subcommand(cmd::stack::show::make_subcommand()),
         )
         .subcommand(
             Command::new("completions")